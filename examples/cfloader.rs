@@ -1,4 +1,4 @@
-use cfloader::{Bllink, CFLoader};
+use cfloader::{Bllink, CFLoader, bootloader};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -15,17 +15,24 @@ async fn main() -> anyhow::Result<()> {
 
     // Example: Read a small portion of STM32 flash for testing
     println!("\n--- Flash Read Test ---");
-    let stm32_start_address = cfloader.stm32_info().flash_start() as u32 * cfloader.stm32_info().page_size() as u32;
+    let stm32_start_address = cfloader.stm32_layout().firmware_start();
     let read_length = 1024u32; // Read 1KB for testing
 
     println!("Reading {} bytes from STM32 at address 0x{:08X}",
              read_length, stm32_start_address);
 
     println!("\n--- Using convenience method ---");
-    let read_back = cfloader.read_stm32_flash(stm32_start_address, 276216).await?;
+    let read_back = cfloader.read_stm32_flash(stm32_start_address, read_length).await?;
     println!("Read {} bytes using convenience method", read_back.len());
-    std::fs::write("stm32_flash_dump.bin", &read_back)?;
-    println!("Read {} bytes from STM32 flash and saved to stm32_flash_dump.bin", read_back.len());
+
+    // Large dumps are streamed to disk as they are read
+    println!("\n--- Streaming the start of the firmware area to a file ---");
+    let dump_length = 276216u32; // Not the whole firmware area, which is close to 1 MB
+    let mut file = tokio::fs::File::create("stm32_flash_dump.bin").await?;
+    let bytes_read = cfloader
+        .read_flash_to_async_writer(bootloader::TARGET_STM32, stm32_start_address, dump_length, &mut file)
+        .await?;
+    println!("Read {} bytes from STM32 flash and saved to stm32_flash_dump.bin", bytes_read);
 
     Ok(())
 }
@@ -79,13 +79,11 @@ async fn main() -> Result<()> {
     println!("\n{}", cfloader.get_bootloader_summary());
     
     // Calculate flash addresses
-    let stm32_page_size = cfloader.stm32_info().page_size() as u32;
-    let stm32_flash_start = cfloader.stm32_info().flash_start() as u32;
-    let stm32_start_address = stm32_flash_start * stm32_page_size;
+    let stm32_page_size = cfloader.stm32_layout().page_size();
+    let stm32_start_address = cfloader.stm32_layout().firmware_start();
     
-    let nrf51_page_size = cfloader.nrf51_info().page_size() as u32;
-    let nrf51_flash_start = cfloader.nrf51_info().flash_start() as u32;
-    let nrf51_start_address = nrf51_flash_start * nrf51_page_size;
+    let nrf51_page_size = cfloader.nrf51_layout().page_size();
+    let nrf51_start_address = cfloader.nrf51_layout().firmware_start();
     
     println!("\nFlash Configuration:");
     println!("STM32 - Page size: {} bytes, Start address: 0x{:08X}", stm32_page_size, stm32_start_address);
@@ -191,7 +189,7 @@ async fn verify_flash(cfloader: &mut CFLoader, target: u8, start_address: u32, b
         
         // Show progress every 10%
         let progress = (bytes_verified as f64 / total_bytes as f64) * 100.0;
-        if bytes_verified % (total_bytes / 10).max(1) == 0 || bytes_verified == 0 {
+        if bytes_verified.is_multiple_of((total_bytes / 10).max(1)) || bytes_verified == 0 {
             print!("\r   {} progress: {:.1}%", target_name, progress);
             io::stdout().flush().unwrap();
         }
@@ -73,20 +73,11 @@ async fn main() -> Result<()> {
         }
     };
     
-    // Get bootloader info for the target
-    let (page_size, flash_start) = match target {
-        bootloader::TARGET_STM32 => {
-            let info = cfloader.stm32_info();
-            (info.page_size() as u32, info.flash_start() as u32)
-        }
-        bootloader::TARGET_NRF51 => {
-            let info = cfloader.nrf51_info();
-            (info.page_size() as u32, info.flash_start() as u32)
-        }
-        _ => unreachable!(),
-    };
-    
-    let start_address = flash_start * page_size;
+    // Get the flash layout of the target
+    let layout = cfloader.layout(target)?;
+    let page_size = layout.page_size();
+    let start_address = layout.firmware_start();
+    let flash_start = layout.position(start_address).page;
     
     println!("\nTarget Information:");
     println!("  Page size: {} bytes", page_size);
@@ -124,11 +115,12 @@ async fn main() -> Result<()> {
         };
         
         match flash_result {
-            Ok(()) => {
+            Ok(report) => {
                 let flash_time = flash_start_time.elapsed();
                 println!("✅ Flash operation completed in {:.2}s ({:.1} KB/s)", 
                          flash_time.as_secs_f64(),
                          (bin_data.len() as f64 / 1024.0) / flash_time.as_secs_f64());
+                println!("   📡 Link: {}", report.link_stats);
             }
             Err(e) => {
                 println!("❌ Flash operation failed after {:.2}s: {}", flash_start_time.elapsed().as_secs_f64(), e);
@@ -221,7 +213,7 @@ async fn verify_flash(cfloader: &mut CFLoader, target: u8, start_address: u32, b
         
         // Show progress every 5%
         let progress = (bytes_verified as f64 / total_bytes as f64) * 100.0;
-        if bytes_verified % (total_bytes / 20).max(1) == 0 || bytes_verified == 0 {
+        if bytes_verified.is_multiple_of((total_bytes / 20).max(1)) || bytes_verified == 0 {
             print!("\r   {} verification: {:.1}% ({}/{} bytes, {} ops)", 
                    target_name, progress, bytes_verified, total_bytes, read_operations);
             io::stdout().flush().unwrap();
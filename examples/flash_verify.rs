@@ -67,20 +67,11 @@ async fn main() -> Result<()> {
         }
     };
     
-    // Get bootloader info for the target
-    let (page_size, flash_start) = match target {
-        bootloader::TARGET_STM32 => {
-            let info = cfloader.stm32_info();
-            (info.page_size() as u32, info.flash_start() as u32)
-        }
-        bootloader::TARGET_NRF51 => {
-            let info = cfloader.nrf51_info();
-            (info.page_size() as u32, info.flash_start() as u32)
-        }
-        _ => unreachable!(),
-    };
-    
-    let start_address = flash_start * page_size;
+    // Get the flash layout of the target
+    let layout = cfloader.layout(target)?;
+    let page_size = layout.page_size();
+    let start_address = layout.firmware_start();
+    let flash_start = layout.position(start_address).page;
     
     println!("\nTarget Information:");
     println!("  Page size: {} bytes", page_size);
@@ -101,6 +92,11 @@ async fn main() -> Result<()> {
             println!("\n❌ Flash verification FAILED!");
             println!("   Verification stopped at first mismatch");
             println!("   Time elapsed: {:.2}ms", start_time.elapsed().as_millis());
+
+            // Read the whole image back to show every differing page
+            println!("\nDifferences with the binary file:");
+            let diff = cfloader.diff_image(target, start_address, &bin_data).await?;
+            println!("{}", diff);
         }
         Err(e) => {
             println!("\n❌ Verification error: {}", e);
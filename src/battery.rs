@@ -0,0 +1,256 @@
+// Battery voltage readings and guard for destructive operations
+//
+// A Crazyflie that runs out of battery while its flash is being written is
+// left with a half-written firmware. The guard reads the battery voltage
+// through the nRF51 bootloader before flashing, and optionally between two
+// flash writes, and stops the operation while it is still safe to do so.
+//
+// The nRF51 bootloader only reports the voltage: the state of the power
+// supply is estimated from it.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::CFLoader;
+
+/// State of the power supply, estimated from the battery voltage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BatteryState {
+    /// Above what a 1S LiPo can hold, the Crazyflie is powered over USB
+    UsbPowered,
+    /// Battery fully charged, or charging over USB
+    Charged,
+    /// Battery voltage in the normal operating range
+    Normal,
+    /// Battery below the default minimum voltage to flash
+    Low,
+    /// Battery about to be cut off by the power management
+    Critical,
+}
+
+impl Display for BatteryState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BatteryState::UsbPowered => write!(f, "USB powered"),
+            BatteryState::Charged => write!(f, "charged"),
+            BatteryState::Normal => write!(f, "normal"),
+            BatteryState::Low => write!(f, "low"),
+            BatteryState::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// Battery voltage measured by the nRF51
+///
+/// Returned by [`Bootloader::get_vbat`](crate::Bootloader::get_vbat) and [`CFLoader::battery`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BatteryReading {
+    /// Battery voltage, in volts
+    pub voltage: f32,
+    /// State of the power supply, estimated from the voltage
+    pub state: BatteryState,
+}
+
+impl BatteryReading {
+    /// Voltage above which the Crazyflie is considered USB powered
+    pub const USB_POWERED_VOLTAGE: f32 = 4.25;
+    /// Voltage above which the battery is considered charged
+    pub const CHARGED_VOLTAGE: f32 = 4.1;
+    /// Voltage below which the battery is considered critical
+    pub const CRITICAL_VOLTAGE: f32 = 3.2;
+
+    /// Create a reading from a voltage, in volts
+    pub fn new(voltage: f32) -> Self {
+        let state = if voltage >= Self::USB_POWERED_VOLTAGE {
+            BatteryState::UsbPowered
+        } else if voltage >= Self::CHARGED_VOLTAGE {
+            BatteryState::Charged
+        } else if voltage >= BatteryGuard::LIPO_1S_MIN_VOLTAGE {
+            BatteryState::Normal
+        } else if voltage >= Self::CRITICAL_VOLTAGE {
+            BatteryState::Low
+        } else {
+            BatteryState::Critical
+        };
+        BatteryReading { voltage, state }
+    }
+
+    /// Check if the Crazyflie seems to be powered over USB
+    pub fn is_usb_powered(&self) -> bool {
+        self.state == BatteryState::UsbPowered
+    }
+
+    /// Check if the battery is low or critical
+    pub fn is_low(&self) -> bool {
+        matches!(self.state, BatteryState::Low | BatteryState::Critical)
+    }
+}
+
+impl Display for BatteryReading {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:.2}V ({})", self.voltage, self.state)
+    }
+}
+
+/// Periodic battery readings
+///
+/// Created by [`CFLoader::monitor_battery`]. The monitor borrows the loader, so it can
+/// only be sampled while no other operation is running.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example(loader: &mut cfloader::CFLoader) -> anyhow::Result<()> {
+/// use std::time::Duration;
+///
+/// let mut monitor = loader.monitor_battery(Duration::from_secs(1));
+/// for _ in 0..10 {
+///     let reading = monitor.next().await?;
+///     println!("{}", reading);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct BatteryMonitor<'a> {
+    loader: &'a mut CFLoader,
+    interval: tokio::time::Interval,
+}
+
+impl<'a> BatteryMonitor<'a> {
+    pub(crate) fn new(loader: &'a mut CFLoader, period: Duration) -> Self {
+        let mut interval = tokio::time::interval(period);
+        // A slow link delays the next reading instead of bursting to catch up
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        BatteryMonitor { loader, interval }
+    }
+
+    /// Wait for the next period and read the battery
+    ///
+    /// The first reading is taken immediately.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nRF51 bootloader does not answer
+    pub async fn next(&mut self) -> anyhow::Result<BatteryReading> {
+        self.interval.tick().await;
+        self.loader.battery().await
+    }
+}
+
+/// Minimum battery voltage required to flash, and how often to check it
+///
+/// The voltage is measured by the nRF51 with the GETVBAT command. When the
+/// voltage is below the minimum before the first page is written, the flash
+/// operation is refused. When `recheck` is enabled the voltage is measured again
+/// before each WRITE_FLASH command and the operation is stopped at that safe point
+/// if it has dropped below the minimum.
+///
+/// # Example
+///
+/// ```
+/// use cfloader::BatteryGuard;
+///
+/// // Crazyflie running on its 1S LiPo, checked before each flash write
+/// let guard = BatteryGuard::lipo_1s().with_recheck(true);
+/// assert_eq!(guard.min_voltage(), BatteryGuard::LIPO_1S_MIN_VOLTAGE);
+///
+/// // Custom threshold
+/// let guard = BatteryGuard::lipo_1s().with_min_voltage(3.7);
+/// assert_eq!(guard.min_voltage(), 3.7);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatteryGuard {
+    min_voltage: f32,
+    recheck: bool,
+}
+
+impl BatteryGuard {
+    /// Default minimum voltage of a Crazyflie running on a 1S LiPo battery, in volts
+    pub const LIPO_1S_MIN_VOLTAGE: f32 = 3.5;
+
+    /// Default minimum voltage of a Crazyflie powered over USB, in volts
+    ///
+    /// The charger keeps the voltage close to 4.2V when USB is connected, a lower value
+    /// means that USB power is not actually available.
+    pub const USB_POWERED_MIN_VOLTAGE: f32 = 4.0;
+
+    /// Guard for a Crazyflie running on a 1S LiPo battery
+    pub fn lipo_1s() -> Self {
+        BatteryGuard {
+            min_voltage: Self::LIPO_1S_MIN_VOLTAGE,
+            recheck: false,
+        }
+    }
+
+    /// Guard for a Crazyflie powered over USB
+    pub fn usb_powered() -> Self {
+        BatteryGuard {
+            min_voltage: Self::USB_POWERED_MIN_VOLTAGE,
+            recheck: false,
+        }
+    }
+
+    /// Set the minimum voltage, in volts
+    pub fn with_min_voltage(mut self, min_voltage: f32) -> Self {
+        self.min_voltage = min_voltage;
+        self
+    }
+
+    /// Enable or disable the voltage check before each flash write
+    pub fn with_recheck(mut self, recheck: bool) -> Self {
+        self.recheck = recheck;
+        self
+    }
+
+    /// Minimum voltage, in volts
+    pub fn min_voltage(&self) -> f32 {
+        self.min_voltage
+    }
+
+    /// Whether the voltage is checked again before each flash write
+    pub fn recheck(&self) -> bool {
+        self.recheck
+    }
+}
+
+impl Default for BatteryGuard {
+    fn default() -> Self {
+        Self::lipo_1s()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Readings just below and at a threshold
+    fn states_around(threshold: f32) -> (BatteryState, BatteryState) {
+        (BatteryReading::new(threshold - 0.01).state, BatteryReading::new(threshold).state)
+    }
+
+    #[test]
+    fn states_change_at_each_threshold() {
+        assert_eq!(states_around(BatteryReading::USB_POWERED_VOLTAGE), (BatteryState::Charged, BatteryState::UsbPowered));
+        assert_eq!(states_around(BatteryReading::CHARGED_VOLTAGE), (BatteryState::Normal, BatteryState::Charged));
+        assert_eq!(states_around(BatteryGuard::LIPO_1S_MIN_VOLTAGE), (BatteryState::Low, BatteryState::Normal));
+        assert_eq!(states_around(BatteryReading::CRITICAL_VOLTAGE), (BatteryState::Critical, BatteryState::Low));
+        assert_eq!(BatteryReading::new(0.0).state, BatteryState::Critical);
+    }
+
+    #[test]
+    fn low_and_usb_powered_follow_the_state() {
+        assert!(BatteryReading::new(4.3).is_usb_powered());
+        assert!(!BatteryReading::new(4.2).is_usb_powered());
+        assert!(!BatteryReading::new(3.5).is_low());
+        assert!(BatteryReading::new(3.4).is_low());
+        assert!(BatteryReading::new(3.0).is_low());
+    }
+
+    #[test]
+    fn display_shows_voltage_and_state() {
+        assert_eq!(BatteryReading::new(3.71).to_string(), "3.71V (normal)");
+        assert_eq!(BatteryReading::new(4.3).to_string(), "4.30V (USB powered)");
+    }
+}
@@ -0,0 +1,808 @@
+// Command line tool to work with the Crazyflie 2.x bootloaders
+//
+// Every subcommand connects to the bootloader over Crazyradio, performs one
+// operation with CFLoader and exits with a code describing the failure, if any.
+// With --json, the result of the operation is printed as one JSON document.
+
+use std::fmt::Display;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use cfloader::{BatteryGuard, BatteryReading, Bllink, Bootloader, CFLoader, CancellationToken, FirmwareInventory, FlashDiff, FlashDigest, FlashReport, ProgressEvent, UpdateReport, VerifyReport, bootloader};
+use cfloader::bootloader::ResetMode;
+use cfloader::error::{AbortReason, OperationAborted, VerificationFailed};
+use cfloader::firmware::{self, RadioUri};
+use cfloader::packets::InfoPacket;
+use clap::{Parser, Subcommand, ValueEnum};
+use crazyradio::{Crazyradio, SharedCrazyradio};
+use serde::Serialize;
+
+/// Program and inspect Crazyflie 2.x quadcopters in bootloader mode
+#[derive(Parser)]
+#[command(name = "cfloader", version, about)]
+struct Cli {
+    /// Index of the Crazyradio to use
+    #[arg(long, global = true, default_value_t = 0)]
+    radio: usize,
+
+    /// Radio address of the bootloader, as 10 hex digits
+    #[arg(long, global = true, value_parser = parse_radio_address)]
+    radio_address: Option<[u8; 5]>,
+
+    /// Reboot the Crazyflie into bootloader mode from its firmware at this radio URI
+    /// (for example radio://0/80/2M/E7E7E7E7E7) before running the command. The radio
+    /// index of the URI selects the Crazyradio in place of --radio
+    #[arg(long, global = true, value_name = "URI")]
+    from_firmware: Option<RadioUri>,
+
+    /// Power source of the Crazyflie, selects the minimum battery voltage to flash
+    #[arg(long, global = true, value_enum, default_value_t = Power::Lipo)]
+    power: Power,
+
+    /// Minimum battery voltage to flash, in volts, overrides the default of --power
+    #[arg(long, global = true)]
+    min_vbat: Option<f32>,
+
+    /// Read back the bootloader RAM buffer before each flash write and reload corrupted data
+    #[arg(long, global = true)]
+    check_buffer: bool,
+
+    /// Allow flash operations to overwrite protected regions, such as the STM32 storage sector
+    #[arg(long, global = true)]
+    allow_protected: bool,
+
+    /// Print the result as a JSON document on stdout instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print information about both bootloaders
+    Info,
+    /// Flash an image
+    Flash {
+        /// Binary image to flash
+        file: PathBuf,
+        #[command(flatten)]
+        location: Location,
+    },
+    /// Read flash content to a file
+    Read {
+        /// Destination file
+        file: PathBuf,
+        #[command(flatten)]
+        location: Location,
+        /// Number of bytes to read
+        #[arg(short, long, value_parser = parse_number)]
+        length: u32,
+    },
+    /// Compare flash content with an image
+    Verify {
+        /// Binary image to compare with
+        file: PathBuf,
+        #[command(flatten)]
+        location: Location,
+    },
+    /// Compare flash content with an image and show the differing pages
+    Diff {
+        /// Binary image to compare with
+        file: PathBuf,
+        #[command(flatten)]
+        location: Location,
+    },
+    /// Flash and verify the firmware of both chips, then reset to firmware
+    #[command(group = clap::ArgGroup::new("images").required(true).multiple(true))]
+    Update {
+        /// STM32 firmware image
+        #[arg(long, group = "images")]
+        stm32: Option<PathBuf>,
+        /// nRF51 firmware image
+        #[arg(long, group = "images")]
+        nrf51: Option<PathBuf>,
+    },
+    /// Identify the installed firmware, optionally comparing it with a release
+    Identify {
+        /// STM32 release image to compare with
+        #[arg(long)]
+        stm32: Option<PathBuf>,
+        /// nRF51 release image to compare with
+        #[arg(long)]
+        nrf51: Option<PathBuf>,
+    },
+    /// Print the CRC32 and SHA-256 of a flash range
+    Hash {
+        #[command(flatten)]
+        location: Location,
+        /// Number of bytes to hash
+        #[arg(short, long, value_parser = parse_number)]
+        length: u32,
+    },
+    /// Reset the Crazyflie to its firmware, or restart its bootloader
+    Reset {
+        /// Restart the bootloader instead of booting the firmware
+        #[arg(long)]
+        bootloader: bool,
+    },
+    /// Look for a Crazyflie bootloader on the bootloader channel
+    Scan,
+    /// Save the whole firmware area of a target to a file
+    Backup {
+        /// Destination file
+        file: PathBuf,
+        /// Bootloader target
+        #[arg(short, long, value_enum, default_value_t = Target::Stm32)]
+        target: Target,
+    },
+    /// Write back a firmware area saved with `backup`, keeping the protected regions
+    /// unless --allow-protected is given
+    Restore {
+        /// Backup file
+        file: PathBuf,
+        /// Bootloader target
+        #[arg(short, long, value_enum, default_value_t = Target::Stm32)]
+        target: Target,
+    },
+    /// Erase flash pages, by default the whole firmware area outside of protected regions
+    Erase {
+        /// Bootloader target
+        #[arg(short, long, value_enum, default_value_t = Target::Stm32)]
+        target: Target,
+        /// First page to erase
+        #[arg(long, requires = "count", value_parser = parse_number)]
+        first_page: Option<u32>,
+        /// Number of pages to erase
+        #[arg(long, requires = "first_page", value_parser = parse_number)]
+        count: Option<u32>,
+    },
+    /// Print the battery voltage
+    Vbat,
+    /// Control the power of the Crazyflie
+    ///
+    /// The STM32 is power cycled first if its bootloader does not answer.
+    Power {
+        #[arg(value_enum)]
+        action: PowerAction,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum PowerAction {
+    /// Power off the whole Crazyflie
+    Off,
+    /// Power cycle the STM32 and wait for its bootloader
+    Cycle,
+    /// Power off the STM32
+    Stm32Off,
+    /// Power on the STM32 and wait for its bootloader
+    Stm32On,
+}
+
+/// Target and address of a flash operation
+#[derive(clap::Args)]
+struct Location {
+    /// Bootloader target
+    #[arg(short, long, value_enum, default_value_t = Target::Stm32)]
+    target: Target,
+    /// Flash address, defaults to the start of the firmware area of the target
+    #[arg(short, long, value_parser = parse_number)]
+    address: Option<u32>,
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Info => "info",
+            Command::Flash { .. } => "flash",
+            Command::Read { .. } => "read",
+            Command::Verify { .. } => "verify",
+            Command::Diff { .. } => "diff",
+            Command::Update { .. } => "update",
+            Command::Identify { .. } => "identify",
+            Command::Hash { .. } => "hash",
+            Command::Reset { .. } => "reset",
+            Command::Scan => "scan",
+            Command::Backup { .. } => "backup",
+            Command::Restore { .. } => "restore",
+            Command::Erase { .. } => "erase",
+            Command::Vbat => "vbat",
+            Command::Power { .. } => "power",
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Target {
+    Stm32,
+    Nrf51,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Power {
+    /// 1S LiPo battery
+    Lipo,
+    /// USB power
+    Usb,
+    /// Do not check the battery voltage
+    Unchecked,
+}
+
+impl Power {
+    fn battery_guard(self, min_vbat: Option<f32>) -> Option<BatteryGuard> {
+        let guard = match self {
+            Power::Lipo => BatteryGuard::lipo_1s(),
+            Power::Usb => BatteryGuard::usb_powered(),
+            Power::Unchecked => return None,
+        };
+        let guard = guard.with_recheck(true);
+        Some(match min_vbat {
+            Some(min_vbat) => guard.with_min_voltage(min_vbat),
+            None => guard,
+        })
+    }
+}
+
+impl Target {
+    fn id(self) -> u8 {
+        match self {
+            Target::Stm32 => bootloader::TARGET_STM32,
+            Target::Nrf51 => bootloader::TARGET_NRF51,
+        }
+    }
+}
+
+/// Class of failure, each one mapping to a process exit code
+///
+/// Exit code 2 is used by the argument parser for usage errors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Failure {
+    /// The Crazyradio could not be opened
+    Radio = 3,
+    /// The bootloader does not answer
+    Connection = 4,
+    /// Reading or writing a local file failed
+    File = 5,
+    /// A flash or read operation failed
+    Operation = 6,
+    /// Flash content does not match the image
+    Mismatch = 7,
+    /// The operation has been cancelled by the user
+    Aborted = 8,
+    /// The battery voltage is too low to flash
+    LowBattery = 9,
+}
+
+struct Error {
+    failure: Failure,
+    error: anyhow::Error,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// Tag an error with the class of failure it represents
+trait Classify<T> {
+    fn or_fail(self, failure: Failure) -> Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> Classify<T> for std::result::Result<T, E> {
+    fn or_fail(self, failure: Failure) -> Result<T> {
+        self.map_err(|e| {
+            let error = e.into();
+            // Aborted operations are reported as such whatever the operation was
+            let failure = match error.downcast_ref::<OperationAborted>() {
+                Some(aborted) if matches!(aborted.reason, AbortReason::LowBattery { .. }) => Failure::LowBattery,
+                Some(_) => Failure::Aborted,
+                None if error.downcast_ref::<VerificationFailed>().is_some() => Failure::Mismatch,
+                None => failure,
+            };
+            Error { failure, error }
+        })
+    }
+}
+
+fn fail<T>(failure: Failure, error: anyhow::Error) -> Result<T> {
+    Err(Error { failure, error })
+}
+
+/// Result of a successfully run command
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Outcome {
+    Info {
+        nrf51: InfoPacket,
+        stm32: InfoPacket,
+        #[serde(skip)]
+        summary: String,
+    },
+    Flash(FlashReport),
+    Read { file: PathBuf, address: u32, length: usize },
+    Verify(VerifyReport),
+    Diff(FlashDiff),
+    Update(Box<UpdateReport>),
+    Identify {
+        installed: FirmwareInventory,
+        // Whether the installed firmware is the given release, if any
+        matches_release: Option<bool>,
+    },
+    Hash(FlashDigest),
+    Reset { mode: ResetMode },
+    Scan { nrf51: Option<InfoPacket>, stm32: Option<InfoPacket> },
+    Backup { file: PathBuf, address: u32, length: usize },
+    Restore { flash: FlashReport, verify: VerifyReport },
+    Erase(Vec<FlashReport>),
+    Vbat(BatteryReading),
+    Power { action: PowerAction },
+}
+
+impl Outcome {
+    // Failure carried by a completed operation, such as a verification mismatch
+    fn failure(&self) -> Option<Error> {
+        let verify = match self {
+            Outcome::Verify(verify) | Outcome::Restore { verify, .. } => verify,
+            Outcome::Diff(diff) if !diff.is_match() => {
+                let error = anyhow::anyhow!("{} of {} bytes differ", diff.differing_bytes, diff.length);
+                return Some(Error { failure: Failure::Mismatch, error });
+            }
+            Outcome::Identify { matches_release: Some(false), .. } => {
+                let error = anyhow::anyhow!("Installed firmware does not match the release");
+                return Some(Error { failure: Failure::Mismatch, error });
+            }
+            _ => return None,
+        };
+        if verify.is_match() {
+            None
+        } else {
+            Some(Error { failure: Failure::Mismatch, error: anyhow::anyhow!("{}", verify) })
+        }
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Outcome::Info { summary, .. } => write!(f, "{}", summary),
+            Outcome::Flash(report) => write!(f, "{}", report),
+            Outcome::Read { file, address, length } => {
+                write!(f, "Read {} bytes from 0x{:08X} to {}", length, address, file.display())
+            }
+            Outcome::Verify(report) => write!(f, "{}", report),
+            Outcome::Diff(diff) => write!(f, "{}", diff),
+            Outcome::Update(report) => write!(f, "{}", report),
+            Outcome::Identify { installed, matches_release } => {
+                write!(f, "{}", installed)?;
+                match matches_release {
+                    Some(true) => write!(f, "\nInstalled firmware matches the release"),
+                    Some(false) => write!(f, "\nInstalled firmware does not match the release"),
+                    None => Ok(()),
+                }
+            }
+            Outcome::Hash(digest) => write!(f, "{}", digest),
+            Outcome::Reset { mode: ResetMode::Firmware } => write!(f, "Crazyflie reset to firmware"),
+            Outcome::Reset { mode: ResetMode::Bootloader } => write!(f, "Crazyflie bootloader restarted"),
+            Outcome::Scan { nrf51, stm32 } => {
+                let found = [("nRF51", nrf51), ("STM32", stm32)];
+                let lines: Vec<String> = found
+                    .iter()
+                    .filter_map(|(name, info)| info.as_ref().map(|info| format!("Found {} bootloader: {}", name, info)))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Outcome::Backup { file, address, length } => {
+                write!(f, "Saved {} bytes from 0x{:08X} to {}", length, address, file.display())
+            }
+            Outcome::Restore { flash, verify } => write!(f, "{}\n{}", flash, verify),
+            Outcome::Erase(reports) => {
+                let lines: Vec<String> = reports.iter().map(|report| report.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Outcome::Vbat(reading) => write!(f, "{}", reading),
+            Outcome::Power { action } => match action {
+                PowerAction::Off => write!(f, "Crazyflie powered off"),
+                PowerAction::Cycle => write!(f, "STM32 power cycled, bootloader answering"),
+                PowerAction::Stm32Off => write!(f, "STM32 powered off"),
+                PowerAction::Stm32On => write!(f, "STM32 powered on, bootloader answering"),
+            },
+        }
+    }
+}
+
+/// JSON document printed with --json
+#[derive(Serialize)]
+struct Document<'a> {
+    command: &'static str,
+    success: bool,
+    exit_code: u8,
+    result: Option<&'a Outcome>,
+    error: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+    let command = cli.command.name();
+
+    let (outcome, error) = match run(cli).await {
+        Ok(outcome) => {
+            let error = outcome.failure();
+            (Some(outcome), error)
+        }
+        Err(e) => (None, Some(e)),
+    };
+    let exit_code = error.as_ref().map_or(0, |e| e.failure as u8);
+
+    if json {
+        let document = Document {
+            command,
+            success: error.is_none(),
+            exit_code,
+            result: outcome.as_ref(),
+            error: error.as_ref().map(|e| format!("{:#}", e.error)),
+        };
+        match serde_json::to_string_pretty(&document) {
+            Ok(document) => println!("{}", document),
+            Err(e) => eprintln!("Error: cannot serialize result: {}", e),
+        }
+    } else {
+        if let Some(outcome) = &outcome {
+            println!("{}", outcome);
+        }
+        if let Some(e) = &error {
+            eprintln!("Error: {:#}", e.error);
+        }
+    }
+
+    ExitCode::from(exit_code)
+}
+
+async fn run(cli: Cli) -> Result<Outcome> {
+    let radio_index = cli.from_firmware.map_or(cli.radio, |uri| uri.radio);
+    let radio = Crazyradio::open_nth_async(radio_index).await.or_fail(Failure::Radio)?;
+    let radio = SharedCrazyradio::new(radio);
+
+    let mut bllink = match &cli.from_firmware {
+        Some(uri) => firmware::enter_bootloader(radio, uri).await.or_fail(Failure::Connection)?,
+        None => Bllink::new_with_radio(radio, cli.radio_address.as_ref()).await.or_fail(Failure::Radio)?,
+    };
+
+    match cli.command {
+        Command::Scan => return scan(bllink).await,
+        Command::Vbat => {
+            let reading = Bootloader::nrf51().get_vbat(&mut bllink).await.or_fail(Failure::Connection)?;
+            return Ok(Outcome::Vbat(reading));
+        }
+        _ => (),
+    }
+
+    let mut loader = match cli.command {
+        // Power control is how a wedged STM32 bootloader gets recovered
+        Command::Power { .. } => CFLoader::new_with_stm32_recovery(bllink).await,
+        _ => CFLoader::new(bllink).await,
+    }.or_fail(Failure::Connection)?;
+
+    // Ctrl-C stops long operations at a safe point
+    let token = CancellationToken::new();
+    loader.set_cancellation_token(Some(token.clone()));
+    loader.set_battery_guard(cli.power.battery_guard(cli.min_vbat));
+    loader.set_buffer_readback(cli.check_buffer);
+    loader.set_allow_protected_writes(cli.allow_protected);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            token.cancel();
+        }
+    });
+
+    // Progress is only shown to humans
+    let progress = if cli.json { None } else { Some(print_progress as fn(&ProgressEvent)) };
+
+    let outcome = match cli.command {
+        Command::Info => Outcome::Info {
+            nrf51: loader.nrf51_info().clone(),
+            stm32: loader.stm32_info().clone(),
+            summary: loader.get_bootloader_summary(),
+        },
+        Command::Flash { file, location } => {
+            let image = std::fs::read(&file).or_fail(Failure::File)?;
+            let address = firmware_address(&loader, &location);
+            Outcome::Flash(flash(&mut loader, location.target, address, &image, progress).await?)
+        }
+        Command::Read { file, location, length } => {
+            let address = firmware_address(&loader, &location);
+            let length = read(&mut loader, location.target, address, length, &file, progress).await?;
+            Outcome::Read { file, address, length }
+        }
+        Command::Verify { file, location } => {
+            let image = std::fs::read(&file).or_fail(Failure::File)?;
+            let address = firmware_address(&loader, &location);
+            Outcome::Verify(verify(&mut loader, location.target, address, &image, progress).await?)
+        }
+        Command::Diff { file, location } => {
+            let image = std::fs::read(&file).or_fail(Failure::File)?;
+            let address = firmware_address(&loader, &location);
+            let diff = loader
+                .diff_image_with_progress(location.target.id(), address, &image, progress)
+                .await
+                .or_fail(Failure::Operation)?;
+            end_progress(progress);
+            Outcome::Diff(diff)
+        }
+        Command::Update { stm32, nrf51 } => {
+            let stm32 = stm32.map(std::fs::read).transpose().or_fail(Failure::File)?;
+            let nrf51 = nrf51.map(std::fs::read).transpose().or_fail(Failure::File)?;
+            let report = loader
+                .update_firmware_with_progress(stm32.as_deref(), nrf51.as_deref(), progress)
+                .await
+                .or_fail(Failure::Operation)?;
+            end_progress(progress);
+            Outcome::Update(Box::new(report))
+        }
+        Command::Identify { stm32, nrf51 } => {
+            let stm32 = stm32.map(std::fs::read).transpose().or_fail(Failure::File)?;
+            let nrf51 = nrf51.map(std::fs::read).transpose().or_fail(Failure::File)?;
+            let installed = loader.identify_installed().await.or_fail(Failure::Operation)?;
+            let matches_release = (stm32.is_some() || nrf51.is_some())
+                .then(|| installed.matches(stm32.as_deref(), nrf51.as_deref()));
+            Outcome::Identify { installed, matches_release }
+        }
+        Command::Hash { location, length } => {
+            let address = firmware_address(&loader, &location);
+            let digest = loader.hash_flash(location.target.id(), address, length).await.or_fail(Failure::Operation)?;
+            Outcome::Hash(digest)
+        }
+        Command::Reset { bootloader } => {
+            let mode = if bootloader { ResetMode::Bootloader } else { ResetMode::Firmware };
+            loader.reset(mode).await.or_fail(Failure::Connection)?;
+            Outcome::Reset { mode }
+        }
+        Command::Backup { file, target } => {
+            let (address, length) = firmware_area(&loader, target);
+            let length = read(&mut loader, target, address, length, &file, progress).await?;
+            Outcome::Backup { file, address, length }
+        }
+        Command::Restore { file, target } => {
+            let image = std::fs::read(&file).or_fail(Failure::File)?;
+            let (address, length) = firmware_area(&loader, target);
+            if image.len() > length as usize {
+                return fail(Failure::File, anyhow::anyhow!(
+                    "Backup file is {} bytes, larger than the {} bytes firmware area", image.len(), length));
+            }
+            if cli.allow_protected {
+                let flash = flash(&mut loader, target, address, &image, progress).await?;
+                let verify = verify(&mut loader, target, address, &image, progress).await?;
+                Outcome::Restore { flash, verify }
+            } else {
+                let restored = loader
+                    .restore_firmware_with_progress(target.id(), &image, progress)
+                    .await
+                    .or_fail(Failure::Operation)?;
+                end_progress(progress);
+                Outcome::Restore { flash: restored.flash, verify: restored.verify }
+            }
+        }
+        Command::Erase { target, first_page, count } => {
+            let reports = match first_page.zip(count) {
+                Some((first_page, count)) => {
+                    let pages = page_range(first_page, count).or_fail(Failure::Operation)?;
+                    vec![loader.erase_pages_with_progress(target.id(), pages, progress).await.or_fail(Failure::Operation)?]
+                }
+                None => loader.erase_firmware_with_progress(target.id(), progress).await.or_fail(Failure::Operation)?,
+            };
+            end_progress(progress);
+            Outcome::Erase(reports)
+        }
+        Command::Power { action } => {
+            match action {
+                PowerAction::Off => loader.power_off().await,
+                PowerAction::Cycle => loader.power_cycle_stm32().await,
+                PowerAction::Stm32Off => loader.stm32_off().await,
+                PowerAction::Stm32On => loader.stm32_on().await,
+            }.or_fail(Failure::Connection)?;
+            Outcome::Power { action }
+        }
+        Command::Scan | Command::Vbat => unreachable!(),
+    };
+
+    Ok(outcome)
+}
+
+async fn scan(mut bllink: Bllink) -> Result<Outcome> {
+    let nrf51 = Bootloader::nrf51().get_info(&mut bllink).await.ok();
+    let stm32 = Bootloader::stm32().get_info(&mut bllink).await.ok();
+
+    if nrf51.is_none() && stm32.is_none() {
+        return fail(Failure::Connection, anyhow::anyhow!("No bootloader found"));
+    }
+    Ok(Outcome::Scan { nrf51, stm32 })
+}
+
+type Progress = Option<fn(&ProgressEvent)>;
+
+async fn flash(loader: &mut CFLoader, target: Target, address: u32, image: &[u8], progress: Progress) -> Result<FlashReport> {
+    let report = loader
+        .flash_image_with_progress(target.id(), address, image, progress)
+        .await
+        .or_fail(Failure::Operation)?;
+    end_progress(progress);
+    Ok(report)
+}
+
+// Stream flash content to a file, returns the number of bytes written
+async fn read(loader: &mut CFLoader, target: Target, address: u32, length: u32, file: &Path, progress: Progress) -> Result<usize> {
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(file).or_fail(Failure::File)?);
+    let bytes_read = loader
+        .read_flash_to_writer_with_progress(target.id(), address, length, &mut writer, progress)
+        .await
+        .or_fail(Failure::Operation)?;
+    end_progress(progress);
+    Ok(bytes_read as usize)
+}
+
+async fn verify(loader: &mut CFLoader, target: Target, address: u32, image: &[u8], progress: Progress) -> Result<VerifyReport> {
+    let report = loader
+        .verify_image_with_progress(target.id(), address, image, progress)
+        .await
+        .or_fail(Failure::Operation)?;
+    end_progress(progress);
+    Ok(report)
+}
+
+fn print_progress(event: &ProgressEvent) {
+    eprint!("\r{:<100}", event.to_string());
+    let _ = std::io::stderr().flush();
+}
+
+// Terminate the progress line once an operation is done
+fn end_progress(progress: Progress) {
+    if progress.is_some() {
+        eprintln!();
+    }
+}
+
+// Pages from `first_page`, checking that they fit in page numbers
+fn page_range(first_page: u32, count: u32) -> anyhow::Result<std::ops::Range<u16>> {
+    let end = first_page.checked_add(count).and_then(|end| u16::try_from(end).ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid page range: {} pages from page {}", count, first_page))?;
+    Ok(first_page as u16..end)
+}
+
+// Address of an operation, the start of the firmware area if not specified
+fn firmware_address(loader: &CFLoader, location: &Location) -> u32 {
+    location.address.unwrap_or_else(|| firmware_area(loader, location.target).0)
+}
+
+// Start address and length of the firmware area of a target
+fn firmware_area(loader: &CFLoader, target: Target) -> (u32, u32) {
+    let layout = match target {
+        Target::Stm32 => loader.stm32_layout(),
+        Target::Nrf51 => loader.nrf51_layout(),
+    };
+    (layout.firmware_start(), layout.firmware_size())
+}
+
+fn parse_number(value: &str) -> std::result::Result<u32, String> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|e| format!("invalid number '{}': {}", value, e))
+}
+
+fn parse_radio_address(value: &str) -> std::result::Result<[u8; 5], String> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    if value.len() != 10 || !value.is_ascii() {
+        return Err(format!("radio address must be 10 hex digits, got '{}'", value));
+    }
+    let mut address = [0u8; 5];
+    for (i, byte) in address.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16)
+            .map_err(|e| format!("invalid radio address '{}': {}", value, e))?;
+    }
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use cfloader::LinkStats;
+    use serde_json::json;
+
+    use super::*;
+
+    fn flash_report() -> FlashReport {
+        FlashReport {
+            target: bootloader::TARGET_STM32,
+            start_address: 0x4000,
+            bytes_written: 2048,
+            first_page: 16,
+            pages_written: 2,
+            buffer_reloads: 0,
+            duration: Duration::from_millis(1500),
+            link_stats: LinkStats::default(),
+        }
+    }
+
+    fn verify_report(mismatched_bytes: usize) -> VerifyReport {
+        let mismatched_ranges: Vec<_> = (mismatched_bytes > 0).then_some(0x4010..0x4010 + mismatched_bytes as u32).into_iter().collect();
+        VerifyReport {
+            target: bootloader::TARGET_STM32,
+            start_address: 0x4000,
+            length: 2048,
+            mismatched_bytes,
+            first_mismatch: mismatched_ranges.first().map(|range| range.start),
+            mismatched_ranges,
+            duration: Duration::from_millis(250),
+            link_stats: LinkStats::default(),
+        }
+    }
+
+    fn link_stats_json() -> serde_json::Value {
+        json!({
+            "packets_sent": 0,
+            "acks_received": 0,
+            "polls": 0,
+            "commands": {},
+            "rtt": { "counts": vec![0u64; 11] },
+        })
+    }
+
+    #[test]
+    fn flash_outcome_is_the_flash_report() {
+        let outcome = serde_json::to_value(Outcome::Flash(flash_report())).unwrap();
+        assert_eq!(outcome, json!({
+            "target": 0xFF,
+            "start_address": 0x4000,
+            "bytes_written": 2048,
+            "first_page": 16,
+            "pages_written": 2,
+            "buffer_reloads": 0,
+            "duration": 1.5,
+            "link_stats": link_stats_json(),
+        }));
+    }
+
+    #[test]
+    fn verify_outcome_is_the_verify_report() {
+        let outcome = serde_json::to_value(Outcome::Verify(verify_report(4))).unwrap();
+        assert_eq!(outcome, json!({
+            "target": 0xFF,
+            "start_address": 0x4000,
+            "length": 2048,
+            "mismatched_bytes": 4,
+            "first_mismatch": 0x4010,
+            "mismatched_ranges": [{ "start": 0x4010, "end": 0x4014 }],
+            "duration": 0.25,
+            "link_stats": link_stats_json(),
+        }));
+
+        let outcome = serde_json::to_value(Outcome::Verify(verify_report(0))).unwrap();
+        assert_eq!(outcome["first_mismatch"], serde_json::Value::Null);
+        assert_eq!(outcome["mismatched_ranges"], json!([]));
+    }
+
+    #[test]
+    fn reset_outcome_names_the_mode() {
+        for (mode, name) in [(ResetMode::Firmware, "Firmware"), (ResetMode::Bootloader, "Bootloader")] {
+            let outcome = serde_json::to_value(Outcome::Reset { mode }).unwrap();
+            assert_eq!(outcome, json!({ "mode": name }));
+        }
+    }
+
+    #[test]
+    fn document_wraps_the_outcome() {
+        let outcome = Outcome::Reset { mode: ResetMode::Firmware };
+        let document = Document { command: "reset", success: true, exit_code: 0, result: Some(&outcome), error: None };
+        assert_eq!(serde_json::to_value(&document).unwrap(), json!({
+            "command": "reset",
+            "success": true,
+            "exit_code": 0,
+            "result": { "mode": "Firmware" },
+            "error": null,
+        }));
+    }
+}
@@ -1,72 +1,288 @@
 use crazyradio::{Crazyradio, SharedCrazyradio};
-use std::time::Duration;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::retry::{CommandClass, RetryPolicies, RetryPolicy};
+use crate::stats::LinkStats;
+use crate::trace::{Trace, TraceRecorder, TraceReplay};
 
 
 /// # Crazyflie bootloader link
-/// 
+///
 /// The bootloader link is very similar to the Crazylfie link over ESB except that it is
 /// based on a very early iteration and does not implement safelink.
-/// 
+///
 /// As such the link requires some special handling in order to work properly. Hence this
 /// implementation is kept separate from crazyflie-link.
-/// 
+///
 /// For simplicity, this implementation is used as a half-duplex link, only sending or
 /// receiving at any one time
+///
+/// Every command is retried according to a [`RetryPolicy`]. The link holds one policy per
+/// [`CommandClass`], see [`retry_policies_mut`](Self::retry_policies_mut) to tune them.
+///
+/// The link keeps [`LinkStats`] about the traffic it handles, see [`stats`](Self::stats).
+///
+/// For debugging, the radio conversation can be recorded to a trace file with
+/// [`record_trace`](Self::record_trace) and later played back without radio with
+/// [`replay`](Self::replay).
 pub struct Bllink {
-    radio: SharedCrazyradio,
+    transport: Transport,
     address: [u8; 5],
     channel: crazyradio::Channel,
+    retry_policies: RetryPolicies,
+    stats: LinkStats,
+    recorder: Option<TraceRecorder>,
+}
+
+// Where the packets of the link go
+enum Transport {
+    Radio(SharedCrazyradio),
+    Replay(TraceReplay),
 }
 
 const DEFAULT_ADDRESS: [u8; 5] = [0xE7, 0xE7, 0xE7, 0xE7, 0xE7];
 const BOOTLOADER_CHANNEL: u8 = 0; // Bootloader channel
-const MAX_RETRIES: usize = 10; // Maximum number of retries for packet transmission
+
+// What a request expects back from the bootloader
+#[derive(Clone, Copy)]
+enum Expect {
+    // Only an ACK from the radio
+    Ack,
+    // A response starting with the whole request
+    Echo,
+    // A response starting with the first n bytes of the request
+    Match(usize),
+}
 
 
 
 impl Bllink {
     /// Create a new Bllink instance
-    /// 
+    ///
     /// This functioon uses the first found Crazyradio USB device to create the link.
-    /// 
+    ///
     /// # Arguments
     /// * `address` - Optional 5-byte address to use for the link. If None, the default address is used.
     ///
     /// # Returns
     /// A Result containing the Bllink instance or an error if the radio could not be opened.
-    /// 
+    ///
     pub async fn new(address: Option<&[u8; 5]>) -> anyhow::Result<Self> {
-        let address = address.unwrap_or(&DEFAULT_ADDRESS);
-
         let radio = Crazyradio::open_first_async().await?;
         let radio = SharedCrazyradio::new(radio);
 
-        Ok(Bllink { radio, channel: crazyradio::Channel::from_number(BOOTLOADER_CHANNEL).unwrap(), address: *address })
+        Self::new_with_radio(radio, address).await
     }
 
     /// Create a new Bllink instance with an existing radio
-    /// 
+    ///
     /// This function creates a Bllink instance using an already-create SharedCrazyradio.
     /// This allows to use the bootloader in a client that already has Crazyradio instances.
-    /// 
+    ///
     /// # Arguments
     /// * `radio` - An existing SharedCrazyradio instance to use for the link
     /// * `address` - Optional 5-byte address to use for the link. If None, the default address is used.
     ///
     /// # Returns
     /// A Result containing the Bllink instance or an error.
-    /// 
+    ///
     pub async fn new_with_radio(radio: SharedCrazyradio,address: Option<&[u8; 5]>) -> anyhow::Result<Self> {
         let address = address.unwrap_or(&DEFAULT_ADDRESS);
 
-        Ok(Bllink { radio, channel: crazyradio::Channel::from_number(BOOTLOADER_CHANNEL).unwrap(), address: *address })
+        Ok(Bllink {
+            transport: Transport::Radio(radio),
+            channel: crazyradio::Channel::from_number(BOOTLOADER_CHANNEL).unwrap(),
+            address: *address,
+            retry_policies: RetryPolicies::default(),
+            stats: LinkStats::default(),
+            recorder: None,
+        })
+    }
+
+    /// Create a Bllink instance playing back a recorded trace
+    ///
+    /// The returned link does not use any radio: each packet sent is checked against the
+    /// next packet of the trace and answered with the recorded ACK status and payload, with
+    /// the recorded timing. This allows to reproduce a field failure offline by running the
+    /// same [`Bootloader`](crate::Bootloader) or [`CFLoader`](crate::CFLoader) calls on top of it.
+    ///
+    /// Sending a packet that differs from the trace, or sending past the end of the trace,
+    /// returns an error.
+    ///
+    /// # Arguments
+    /// * `trace` - The trace to play back, see [`Trace::load`]
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn example() -> anyhow::Result<()> {
+    /// use cfloader::{Bllink, CFLoader, Trace};
+    ///
+    /// let bllink = Bllink::replay(Trace::load("field_failure.trace")?);
+    /// let mut loader = CFLoader::new(bllink).await?;
+    /// let firmware = loader.read_stm32_flash(0x4000, 1024).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn replay(trace: Trace) -> Self {
+        Bllink {
+            transport: Transport::Replay(TraceReplay::new(trace)),
+            channel: crazyradio::Channel::from_number(BOOTLOADER_CHANNEL).unwrap(),
+            address: DEFAULT_ADDRESS,
+            retry_policies: RetryPolicies::default(),
+            stats: LinkStats::default(),
+            recorder: None,
+        }
+    }
+
+    /// Number of trace entries not yet played back
+    ///
+    /// Returns `None` if the link is not playing back a trace.
+    pub fn replay_remaining(&self) -> Option<usize> {
+        match &self.transport {
+            Transport::Radio(_) => None,
+            Transport::Replay(replay) => Some(replay.remaining()),
+        }
+    }
+
+    /// Start recording all packets exchanged by the link to a trace file
+    ///
+    /// The file is created, or truncated if it exists. Any recording in progress is stopped first.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the trace file
+    pub fn record_trace(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path.as_ref())
+            .map_err(|e| anyhow::anyhow!("Cannot create trace file {}: {}", path.as_ref().display(), e))?;
+        self.record_trace_to(std::io::BufWriter::new(file))
+    }
+
+    /// Start recording all packets exchanged by the link to a writer
+    ///
+    /// Any recording in progress is stopped first.
+    ///
+    /// # Arguments
+    /// * `writer` - Destination of the trace, in the format read by [`Trace::from_reader`]
+    pub fn record_trace_to(&mut self, writer: impl Write + Send + 'static) -> anyhow::Result<()> {
+        self.stop_trace()?;
+        self.recorder = Some(TraceRecorder::new(Box::new(writer), &self.address, self.channel.into())?);
+        Ok(())
+    }
+
+    /// Stop recording the trace, flushing it to its destination
+    pub fn stop_trace(&mut self) -> anyhow::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the radio address of the link
+    pub fn address(&self) -> [u8; 5] {
+        self.address
+    }
+
+    /// Change the radio address of the link
+    ///
+    /// This is needed when the bootloader moves to another address, for example after a
+    /// reset into bootloader mode. The new address is used from the next packet on.
+    pub fn set_address(&mut self, address: [u8; 5]) {
+        self.address = address;
+    }
+
+    // Change the radio channel of the link, to talk to a Crazyflie running its firmware
+    pub(crate) fn set_channel(&mut self, channel: crazyradio::Channel) {
+        self.channel = channel;
+    }
+
+    /// Get the retry policies used by this link
+    pub fn retry_policies(&self) -> &RetryPolicies {
+        &self.retry_policies
+    }
+
+    /// Get a mutable reference to the retry policies used by this link
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn example() -> anyhow::Result<()> {
+    /// use std::time::Duration;
+    /// use cfloader::{Bllink, CommandClass};
+    ///
+    /// let mut bllink = Bllink::new(None).await?;
+    /// let query = bllink.retry_policies_mut().get_mut(CommandClass::Query);
+    /// *query = query.with_attempts(30).with_timeout(Duration::from_millis(50));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn retry_policies_mut(&mut self) -> &mut RetryPolicies {
+        &mut self.retry_policies
+    }
+
+    /// Replace the retry policies used by this link
+    pub fn set_retry_policies(&mut self, retry_policies: RetryPolicies) {
+        self.retry_policies = retry_policies;
+    }
+
+    /// Get the retry policy used by this link for a class of command
+    pub fn retry_policy(&self, class: CommandClass) -> RetryPolicy {
+        *self.retry_policies.get(class)
+    }
+
+    /// Get a snapshot of the link statistics
+    ///
+    /// The statistics are accumulated since the link was created or since the last call
+    /// to [`reset_stats`](Self::reset_stats).
+    pub fn stats(&self) -> LinkStats {
+        self.stats.clone()
+    }
+
+    // Total number of retries so far, without copying the statistics
+    pub(crate) fn total_retries(&self) -> u64 {
+        self.stats.total_retries()
+    }
+
+    /// Reset the link statistics
+    pub fn reset_stats(&mut self) {
+        self.stats = LinkStats::default();
+    }
+
+    // Send one radio packet, keeping track of the link statistics
+    async fn send_packet(&mut self, data: Vec<u8>) -> anyhow::Result<(bool, Vec<u8>)> {
+        let (acked, answer) = match &mut self.transport {
+            Transport::Radio(radio) => {
+                let (ack, answer) = radio.send_packet_async(self.channel, self.address, data.clone()).await?;
+                (ack.received, answer)
+            }
+            Transport::Replay(replay) => replay.exchange(&data).await?,
+        };
+
+        self.stats.packets_sent += 1;
+        if acked {
+            self.stats.acks_received += 1;
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(&data, acked, &answer)?;
+        }
+
+        Ok((acked, answer))
+    }
+
+    // Send one poll packet while waiting for a response
+    async fn poll(&mut self) -> anyhow::Result<(bool, Vec<u8>)> {
+        self.stats.polls += 1;
+        self.send_packet(vec![0xff]).await
     }
 
 
     /// Send a packet as request, expect one packet as response matching the request data
     ///
     /// This method sends a packet and waits for a response packet that starts with the same data as the request.
-    /// If no valid response is received within the timeout duration, the request is retried up to MAX_RETRIES times.
+    /// If no valid response is received within the timeout duration, the request is retried according to
+    /// the [`CommandClass::Query`] retry policy of the link.
     ///
     /// # Arguments
     ///
@@ -79,24 +295,32 @@ impl Bllink {
     ///
     /// # Errors
     ///
-    /// Returns an error if no valid response is received after MAX_RETRIES attempts
+    /// Returns an error if no valid response is received after all attempts
     pub async fn request(&mut self, data: &[u8], timeout_duration: Duration) -> anyhow::Result<Vec<u8>> {
-        for attempt in 0..MAX_RETRIES {
-            match self.try_request(data, timeout_duration).await {
-                Ok(response) => return Ok(response),
-                Err(e) => {
-                    if attempt == MAX_RETRIES - 1 {
-                        return Err(anyhow::anyhow!(
-                            "Failed to get response after {} attempts: {}", 
-                            MAX_RETRIES, e
-                        ));
-                    }
-                    // Log retry attempt if desired
-                    //eprintln!("Request attempt {} failed: {}, retrying...", attempt + 1, e);
-                }
-            }
-        }
-        unreachable!()
+        let policy = self.retry_policy(CommandClass::Query).with_timeout(timeout_duration);
+        self.request_with_policy(data, &policy).await
+    }
+
+    /// Send a packet as request with an explicit retry policy
+    ///
+    /// Same as [`request`](Self::request) but the number of attempts, timeout, backoff and
+    /// deadline are taken from `policy`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The packet data to send
+    /// * `policy` - Retry policy to apply
+    ///
+    /// # Returns
+    ///
+    /// A `Vec<u8>` containing the response data
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no valid response is received within the policy limits
+    pub async fn request_with_policy(&mut self, data: &[u8], policy: &RetryPolicy) -> anyhow::Result<Vec<u8>> {
+        self.transact(data, Expect::Echo, policy).await
+            .map_err(|(attempts, e)| anyhow::anyhow!("Failed to get response after {} attempts: {}", attempts, e))
     }
 
     /// Send a packet as request with partial response matching
@@ -117,74 +341,130 @@ impl Bllink {
     ///
     /// # Errors
     ///
-    /// Returns an error if no valid response is received after MAX_RETRIES attempts
+    /// Returns an error if no valid response is received after all attempts
     pub async fn request_match_response(&mut self, data: &[u8], match_length: usize, timeout_duration: Duration) -> anyhow::Result<Vec<u8>> {
-        for attempt in 0..MAX_RETRIES {
-            match self.try_request_match_response(data, match_length, timeout_duration).await {
-                Ok(response) => return Ok(response),
-                Err(e) => {
-                    if attempt == MAX_RETRIES - 1 {
-                        return Err(anyhow::anyhow!(
-                            "Failed to get matching response after {} attempts: {}", 
-                            MAX_RETRIES, e
-                        ));
-                    }
-                    // Log retry attempt if desired
-                    //eprintln!("Request match attempt {} failed: {}, retrying...", attempt + 1, e);
+        let policy = self.retry_policy(CommandClass::Query).with_timeout(timeout_duration);
+        self.request_match_response_with_policy(data, match_length, &policy).await
+    }
+
+    /// Send a packet as request with partial response matching and an explicit retry policy
+    ///
+    /// Same as [`request_match_response`](Self::request_match_response) but the number of
+    /// attempts, timeout, backoff and deadline are taken from `policy`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The packet data to send
+    /// * `match_length` - Number of bytes from the start of the response that must match the request
+    /// * `policy` - Retry policy to apply
+    ///
+    /// # Returns
+    ///
+    /// A `Vec<u8>` containing the response data
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no valid response is received within the policy limits
+    pub async fn request_match_response_with_policy(&mut self, data: &[u8], match_length: usize, policy: &RetryPolicy) -> anyhow::Result<Vec<u8>> {
+        // Validate match_length
+        if match_length > data.len() {
+            return Err(anyhow::anyhow!("match_length {} cannot be greater than data length {}", match_length, data.len()));
+        }
+
+        self.transact(data, Expect::Match(match_length), policy).await
+            .map_err(|(attempts, e)| anyhow::anyhow!("Failed to get matching response after {} attempts: {}", attempts, e))
+    }
+
+    // Run attempts of a request according to the retry policy
+    //
+    // On failure, returns the number of attempts made together with the last error
+    async fn transact(&mut self, data: &[u8], expect: Expect, policy: &RetryPolicy) -> Result<Vec<u8>, (usize, anyhow::Error)> {
+        let deadline = policy.deadline().map(|deadline| Instant::now() + deadline);
+        let command = data.get(2).copied().unwrap_or_default();
+        let mut attempt = 0;
+
+        loop {
+            let attempt_start = Instant::now();
+            let remaining = deadline.map(|deadline| deadline.saturating_duration_since(attempt_start));
+            let timeout_duration = policy.attempt_timeout(remaining);
+
+            let result = match expect {
+                Expect::Ack => self.try_send(data, timeout_duration, policy.poll_interval()).await.map(|_| Vec::new()),
+                Expect::Echo => self.try_request(data, timeout_duration, policy.poll_interval()).await,
+                Expect::Match(match_length) => self.try_request_match_response(data, match_length, timeout_duration, policy.poll_interval()).await,
+            };
+            attempt += 1;
+
+            let e = match result {
+                Ok(response) => {
+                    self.stats.rtt.record(attempt_start.elapsed());
+                    self.stats.record_command(command, attempt, true);
+                    return Ok(response);
                 }
+                Err(e) => e,
+            };
+
+            if attempt >= policy.attempts() {
+                self.stats.record_command(command, attempt, false);
+                return Err((attempt, e));
+            }
+
+            let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let Some(delay) = policy.retry_delay(attempt - 1, remaining) else {
+                self.stats.record_command(command, attempt, false);
+                return Err((attempt, e.context(format!("Deadline of {:?} exceeded", policy.deadline().unwrap()))));
+            };
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
             }
+            // Log retry attempt if desired
+            //eprintln!("Request attempt {} failed: {}, retrying...", attempt, e);
         }
-        unreachable!()
     }
 
     // Internal method to try a single request with partial response matching
-    async fn try_request_match_response(&mut self, data: &[u8], match_length: usize, timeout_duration: Duration) -> anyhow::Result<Vec<u8>> {
-        let start_time = std::time::Instant::now();
+    async fn try_request_match_response(&mut self, data: &[u8], match_length: usize, timeout_duration: Duration, poll_interval: Duration) -> anyhow::Result<Vec<u8>> {
+        let start_time = Instant::now();
         let mut answer = Vec::new();
         let mut got_initial_ack = false;
-        
-        // Validate match_length
-        if match_length > data.len() {
-            return Err(anyhow::anyhow!("match_length {} cannot be greater than data length {}", match_length, data.len()));
-        }
-        
+
         let match_data = &data[..match_length];
-        
+
         // First, send the initial request and wait for ACK within timeout window
         while start_time.elapsed() < timeout_duration && !got_initial_ack {
-            let (ack, response) = self.radio.send_packet_async(self.channel, self.address, data.to_vec()).await
+            let (ack, response) = self.send_packet(data.to_vec()).await
                 .map_err(|e| anyhow::anyhow!("Radio error during initial send: {}", e))?;
 
-            if ack.received {
+            if ack {
                 got_initial_ack = true;
                 answer = response;
             } else {
                 // Short delay before retry
-                tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+                tokio::time::sleep(poll_interval).await;
             }
         }
-        
+
         if !got_initial_ack {
             return Err(anyhow::anyhow!("Timeout: No ACK received for initial packet within {:?}", timeout_duration));
         }
 
         // Keep polling for valid response with remaining timeout
         while start_time.elapsed() < timeout_duration && (answer.len() < match_length || !answer[..match_length].eq(match_data)) {
-            let (new_ack, new_answer) = self.radio.send_packet_async(self.channel, self.address, vec![0xff]).await
+            let (new_ack, new_answer) = self.poll().await
                 .map_err(|e| anyhow::anyhow!("Radio error during polling: {}", e))?;
 
-            if new_ack.received {
+            if new_ack {
                 answer = new_answer;
             }
-            
+
             // Short delay before next poll
-            tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+            tokio::time::sleep(poll_interval).await;
         }
-        
+
         if answer.len() < match_length || !answer[..match_length].eq(match_data) {
             return Err(anyhow::anyhow!(
-                "Timeout: No valid response received within {:?}. Expected first {} bytes to match {:02X?}, got {:02X?}", 
-                timeout_duration, match_length, match_data, 
+                "Timeout: No valid response received within {:?}. Expected first {} bytes to match {:02X?}, got {:02X?}",
+                timeout_duration, match_length, match_data,
                 if answer.len() >= match_length { &answer[..match_length] } else { &answer }
             ));
         }
@@ -193,42 +473,42 @@ impl Bllink {
     }
 
     // Internal method to try a single request with timeout
-    async fn try_request(&mut self, data: &[u8], timeout_duration: Duration) -> anyhow::Result<Vec<u8>> {
-        let start_time = std::time::Instant::now();
+    async fn try_request(&mut self, data: &[u8], timeout_duration: Duration, poll_interval: Duration) -> anyhow::Result<Vec<u8>> {
+        let start_time = Instant::now();
         let mut answer = Vec::new();
         let mut got_initial_ack = false;
-        
+
         // First, send the initial request and wait for ACK within timeout window
         while start_time.elapsed() < timeout_duration && !got_initial_ack {
-            let (ack, response) = self.radio.send_packet_async(self.channel, self.address, data.to_vec()).await
+            let (ack, response) = self.send_packet(data.to_vec()).await
                 .map_err(|e| anyhow::anyhow!("Radio error during initial send: {}", e))?;
 
-            if ack.received {
+            if ack {
                 got_initial_ack = true;
                 answer = response;
             } else {
                 // Short delay before retry
-                tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+                tokio::time::sleep(poll_interval).await;
             }
         }
-        
+
         if !got_initial_ack {
             return Err(anyhow::anyhow!("Timeout: No ACK received for initial packet within {:?}", timeout_duration));
         }
 
         // Keep polling for valid response with remaining timeout
         while start_time.elapsed() < timeout_duration && !answer.starts_with(data) {
-            let (new_ack, new_answer) = self.radio.send_packet_async(self.channel, self.address, vec![0xff]).await
+            let (new_ack, new_answer) = self.poll().await
                 .map_err(|e| anyhow::anyhow!("Radio error during polling: {}", e))?;
 
-            if new_ack.received {
+            if new_ack {
                 answer = new_answer;
             }
-            
+
             // Short delay before next poll
-            tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+            tokio::time::sleep(poll_interval).await;
         }
-        
+
         if !answer.starts_with(data) {
             return Err(anyhow::anyhow!("Timeout: No valid response received within {:?}", timeout_duration));
         }
@@ -239,7 +519,7 @@ impl Bllink {
     /// Send a packet without expecting a response
     ///
     /// Sends a packet and waits only for acknowledgment (ACK) from the radio.
-    /// Uses a default timeout of 1000ms.
+    /// Uses the [`CommandClass::Send`] retry policy of the link (1000ms timeout by default).
     ///
     /// # Arguments
     ///
@@ -249,13 +529,14 @@ impl Bllink {
     ///
     /// An empty result indicating success or failure
     pub async fn send(&mut self, data: &[u8]) -> anyhow::Result<()> {
-        self.send_with_timeout(data, Duration::from_millis(1000)).await
+        let policy = self.retry_policy(CommandClass::Send);
+        self.send_with_policy(data, &policy).await
     }
 
     /// Send a packet with custom timeout, without expecting a response
     ///
     /// Sends a packet and waits only for acknowledgment (ACK) from the radio.
-    /// Retries up to MAX_RETRIES times if no ACK is received.
+    /// Retries according to the [`CommandClass::Send`] retry policy of the link if no ACK is received.
     ///
     /// # Arguments
     ///
@@ -268,40 +549,107 @@ impl Bllink {
     ///
     /// # Errors
     ///
-    /// Returns an error if no ACK is received after MAX_RETRIES attempts
+    /// Returns an error if no ACK is received after all attempts
     pub async fn send_with_timeout(&mut self, data: &[u8], timeout_duration: Duration) -> anyhow::Result<()> {
-        for attempt in 0..MAX_RETRIES {
-            match self.try_send(data, timeout_duration).await {
-                Ok(_) => return Ok(()),
-                Err(e) => {
-                    if attempt == MAX_RETRIES - 1 {
-                        return Err(anyhow::anyhow!(
-                            "Failed to send packet after {} attempts: {}", 
-                            MAX_RETRIES, e
-                        ));
-                    }
-                }
-            }
-        }
-        unreachable!()
+        let policy = self.retry_policy(CommandClass::Send).with_timeout(timeout_duration);
+        self.send_with_policy(data, &policy).await
+    }
+
+    /// Send a packet with an explicit retry policy, without expecting a response
+    ///
+    /// Sends a packet and waits only for acknowledgment (ACK) from the radio.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The packet data to send
+    /// * `policy` - Retry policy to apply
+    ///
+    /// # Returns
+    ///
+    /// An empty result indicating success or failure
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no ACK is received within the policy limits
+    pub async fn send_with_policy(&mut self, data: &[u8], policy: &RetryPolicy) -> anyhow::Result<()> {
+        self.transact(data, Expect::Ack, policy).await
+            .map(|_| ())
+            .map_err(|(attempts, e)| anyhow::anyhow!("Failed to send packet after {} attempts: {}", attempts, e))
     }
 
     // Internal method to try a single send with timeout
-    async fn try_send(&mut self, data: &[u8], timeout_duration: Duration) -> anyhow::Result<()> {
-        let start_time = std::time::Instant::now();
-        
+    async fn try_send(&mut self, data: &[u8], timeout_duration: Duration, poll_interval: Duration) -> anyhow::Result<()> {
+        let start_time = Instant::now();
+
         while start_time.elapsed() < timeout_duration {
-            let (ack, _answer) = self.radio.send_packet_async(self.channel, self.address, data.to_vec()).await
+            let (ack, _answer) = self.send_packet(data.to_vec()).await
                 .map_err(|e| anyhow::anyhow!("Radio error during send: {}", e))?;
 
-            if ack.received {
+            if ack {
                 return Ok(());
             }
-            
+
             // Short delay before retry
-            tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+            tokio::time::sleep(poll_interval).await;
         }
-        
+
         Err(anyhow::anyhow!("Timeout: No ACK received within {:?}", timeout_duration))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bootloader;
+
+    // GET_INFO answer of a Crazyflie 2.1 STM32 bootloader: 1024 bytes pages,
+    // 10 buffer pages, 1024 flash pages starting at page 16, protocol 0x10
+    const STM32_INFO_TRACE: &str = "\
+# cfloader trace, address E7E7E7E7E7, channel 0
+0 FFFF10 0 -
+150 FFFF10 1 FFFF1000040A0000041000000102030405060708090A0B10
+";
+
+    #[tokio::test]
+    async fn replay_drives_get_info() {
+        let mut bllink = Bllink::replay(Trace::from_reader(STM32_INFO_TRACE.as_bytes()).unwrap());
+
+        let info = Bootloader::stm32().get_info(&mut bllink).await.unwrap();
+        assert_eq!(info.page_size(), 1024);
+        assert_eq!(info.n_buff_page(), 10);
+        assert_eq!(info.n_flash_page(), 1024);
+        assert_eq!(info.flash_start(), 16);
+        assert_eq!(info.version(), 0x10);
+
+        assert_eq!(bllink.replay_remaining(), Some(0));
+        let stats = bllink.stats();
+        assert_eq!(stats.packets_sent, 2);
+        assert_eq!(stats.acks_received, 1);
+    }
+
+    #[tokio::test]
+    async fn stats_snapshot_and_reset() {
+        let mut bllink = Bllink::replay(Trace::from_reader(STM32_INFO_TRACE.as_bytes()).unwrap());
+        let before = bllink.stats();
+        Bootloader::stm32().get_info(&mut bllink).await.unwrap();
+
+        let delta = bllink.stats().since(&before);
+        assert_eq!(delta.packets_sent, 2);
+        assert_eq!(delta.commands[&0x10].commands, 1);
+        assert_eq!(delta.rtt.total(), 1);
+
+        bllink.reset_stats();
+        assert_eq!(bllink.stats(), LinkStats::default());
+    }
+
+    #[tokio::test]
+    async fn replay_fails_when_sent_packets_diverge() {
+        let mut bllink = Bllink::replay(Trace::from_reader(STM32_INFO_TRACE.as_bytes()).unwrap());
+        let policy = RetryPolicy::default().with_attempts(1);
+
+        // GET_INFO to the nRF51 while the trace recorded it to the STM32
+        let error = bllink.request_with_policy(&[0xFF, 0xFE, 0x10], &policy).await.unwrap_err();
+        assert!(error.to_string().contains("Replay diverged from trace"), "{}", error);
+        assert_eq!(bllink.replay_remaining(), Some(1));
+    }
+}
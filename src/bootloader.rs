@@ -5,11 +5,15 @@
 //!
 //! For most use cases, prefer using the high-level [`CFLoader`](crate::CFLoader) interface instead.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bllink::Bllink;
 
 use crate::{bllink, packets::*};
+use crate::battery::BatteryReading;
+use crate::capabilities::{Capabilities, OptionalCommand};
+use crate::error::UnsupportedCommand;
+use crate::retry::{CommandClass, RetryPolicies, RetryPolicy};
 
 // Bootloader command constants
 const CMD_GET_INFO: u8 = 0x10;
@@ -32,23 +36,52 @@ pub const TARGET_STM32: u8 = 0xFF;
 /// nRF51 bootloader target identifier
 pub const TARGET_NRF51: u8 = 0xFE;
 
-// Default short timeout for bootloader operations that should return directly
-const SHORT_TIMEOUT: Duration = Duration::from_millis(10);
-// Timeout for flash operation, flash operation can take up to one second to complete
-const FLASH_TIMEOUT: Duration = Duration::from_secs(2);
+/// Maximum number of data bytes of a LOAD_BUFFER command and of a READ_FLASH or READ_BUFFER answer
+///
+/// Radio packets carry 32 bytes, these commands use 7 of them for
+/// `[0xFF, target, command, page (2 bytes), address (2 bytes)]`. A bootloader may use less,
+/// see [`Capabilities::max_load_payload`] and [`Capabilities::max_read_payload`].
+pub const MAX_PAYLOAD: usize = 25;
+
+/// What the Crazyflie boots into after a [`Bootloader::reset`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum ResetMode {
+    /// Boot the normal firmware
+    Firmware,
+    /// Restart the bootloader
+    Bootloader,
+}
+
+impl ResetMode {
+    // Mode byte of the RESET command
+    fn byte(self) -> u8 {
+        match self {
+            ResetMode::Firmware => 0x01,
+            ResetMode::Bootloader => 0x00,
+        }
+    }
+}
 
 /// Bootloader interface for Crazyflie 2.x platform
 /// 
 /// The Crazyflie 2.x platform has 2 bootloaders: one in the nRF51822 and one in the STM32F405.
 /// This struct provides a unified interface to communicate with either bootloader.
+///
+/// Commands are retried using the retry policies of the [`Bllink`] unless policies
+/// are set on the bootloader itself with [`set_retry_policies`](Self::set_retry_policies).
+///
+/// Once its [`Capabilities`] are set, the bootloader refuses the commands its protocol
+/// version does not implement with an [`UnsupportedCommand`] error.
 pub struct Bootloader {
     target: u8,
+    retry_policies: Option<RetryPolicies>,
+    capabilities: Option<Capabilities>,
 }
 
 impl Bootloader {
     /// Create a new bootloader interface for the given target
     pub fn new(target: u8) -> Self {
-        Bootloader { target }
+        Bootloader { target, retry_policies: None, capabilities: None }
     }
 
     /// Create a bootloader for the STM32 target (0xFF)
@@ -66,6 +99,50 @@ impl Bootloader {
         self.target
     }
 
+    /// Get the retry policies overriding the ones of the link, if any
+    pub fn retry_policies(&self) -> Option<&RetryPolicies> {
+        self.retry_policies.as_ref()
+    }
+
+    /// Override the retry policies of the link for the commands sent to this bootloader
+    ///
+    /// Passing `None` reverts to the retry policies of the [`Bllink`].
+    pub fn set_retry_policies(&mut self, retry_policies: Option<RetryPolicies>) {
+        self.retry_policies = retry_policies;
+    }
+
+    /// Get the capabilities of the bootloader, if known
+    pub fn capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.as_ref()
+    }
+
+    /// Set the capabilities of the bootloader, usually from its [`InfoPacket`]
+    ///
+    /// Passing `None` sends every command and allows [`MAX_PAYLOAD`] bytes loads, which is the default.
+    pub fn set_capabilities(&mut self, capabilities: Option<Capabilities>) {
+        self.capabilities = capabilities;
+    }
+
+    // Refuse a command the bootloader is known not to implement
+    fn require(&self, command: OptionalCommand) -> anyhow::Result<()> {
+        match self.capabilities {
+            Some(capabilities) if !capabilities.supports(command) => Err(UnsupportedCommand {
+                target: self.target,
+                version: capabilities.version(),
+                command,
+            }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // Retry policy to use for a class of command
+    fn policy(&self, bllink: &Bllink, class: CommandClass) -> RetryPolicy {
+        match &self.retry_policies {
+            Some(policies) => *policies.get(class),
+            None => bllink.retry_policy(class),
+        }
+    }
+
     /// Get bootloader information
     /// 
     /// # Arguments
@@ -77,7 +154,8 @@ impl Bootloader {
     /// An [InfoPacket] containing the bootloader information
     pub async fn get_info(&self, bllink: &mut Bllink) -> anyhow::Result<InfoPacket> {
         let get_info_command = vec![0xff, self.target, CMD_GET_INFO];
-        let response = bllink.request(&get_info_command, SHORT_TIMEOUT).await?;
+        let policy = self.policy(bllink, CommandClass::Query);
+        let response = bllink.request_with_policy(&get_info_command, &policy).await?;
         Ok(InfoPacket::from_bytes(&response[2..]))
     }
 
@@ -91,10 +169,17 @@ impl Bootloader {
     /// # Returns
     /// 
     /// An empty result indicating success or failure
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`UnsupportedCommand`] error if the capabilities of the bootloader
+    /// exclude SET_ADDRESS
     pub async fn set_address(&self, bllink: &mut Bllink, address: &[u8; 5]) -> anyhow::Result<()> {
+        self.require(OptionalCommand::SetAddress)?;
         let mut command = vec![0xff, self.target, CMD_SET_ADDRESS];
         command.extend_from_slice(address);
-        bllink.send(&command).await?;
+        let policy = self.policy(bllink, CommandClass::Send);
+        bllink.send_with_policy(&command, &policy).await?;
         Ok(())
     }
 
@@ -109,9 +194,16 @@ impl Bootloader {
     /// # Returns
     ///
     /// A vector containing the raw mapping data bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`UnsupportedCommand`] error if the capabilities of the bootloader
+    /// exclude GET_MAPPING, as on the nRF51
     pub async fn get_mapping(&self, bllink: &mut Bllink) -> anyhow::Result<Vec<u8>> {
+        self.require(OptionalCommand::GetMapping)?;
         let command = vec![0xff, self.target, CMD_GET_MAPPING];
-        let response = bllink.request(&command, SHORT_TIMEOUT).await?;
+        let policy = self.policy(bllink, CommandClass::Query);
+        let response = bllink.request_with_policy(&command, &policy).await?;
         // Skip the first byte (command echo) and return the mapping data
         Ok(response[1..].to_vec())
     }
@@ -126,7 +218,7 @@ impl Bootloader {
     /// * `bllink` - The Bllink interface to use for communication
     /// * `page` - The page number in the buffer
     /// * `address` - The address offset within the page
-    /// * `data` - The data to load (maximum 25 bytes)
+    /// * `data` - The data to load (maximum [`Capabilities::max_load_payload`], [`MAX_PAYLOAD`] bytes by default)
     ///
     /// # Returns
     ///
@@ -134,10 +226,11 @@ impl Bootloader {
     ///
     /// # Errors
     ///
-    /// Returns an error if `data` is longer than 25 bytes
+    /// Returns an error if `data` is longer than the maximum payload
     pub async fn load_buffer(&self, bllink: &mut Bllink, page: u16, address: u16, data: &[u8]) -> anyhow::Result<()> {
-        if data.len() > 25 {
-            return Err(anyhow::anyhow!("Data too large for buffer load (max 25 bytes)"));
+        let max_payload = self.capabilities.map_or(MAX_PAYLOAD, |capabilities| capabilities.max_load_payload());
+        if data.len() > max_payload {
+            return Err(anyhow::anyhow!("Data too large for buffer load (max {} bytes)", max_payload));
         }
         
         let mut command = vec![0xff, self.target, CMD_LOAD_BUFFER];
@@ -146,7 +239,8 @@ impl Bootloader {
         command.extend_from_slice(data);
         
         // Simple send with ACK - no detailed response validation since it's just an ACK
-        bllink.send(&command).await?;
+        let policy = self.policy(bllink, CommandClass::Send);
+        bllink.send_with_policy(&command, &policy).await?;
         Ok(())
     }
 
@@ -168,7 +262,8 @@ impl Bootloader {
         command.extend_from_slice(&page.to_le_bytes());
         command.extend_from_slice(&address.to_le_bytes());
         
-        let response = bllink.request(&command, SHORT_TIMEOUT).await?;
+        let policy = self.policy(bllink, CommandClass::Query);
+        let response = bllink.request_with_policy(&command, &policy).await?;
         Ok(BufferReadPacket::from_bytes(&response[2..]))
     }
 
@@ -195,7 +290,8 @@ impl Bootloader {
         
         // TODO: When flashing, if the ack is lost, we should send again a flash status request and not a flash
         //       This is because flash reequest both takes a lot of time and utilize flash endurance of the chip.
-        let response = bllink.request_match_response(&command, 3, FLASH_TIMEOUT).await?;
+        let policy = self.policy(bllink, CommandClass::Flash);
+        let response = bllink.request_match_response_with_policy(&command, 3, &policy).await?;
         Ok(FlashWriteResponse::from_bytes(&response[2..]))
     }
 
@@ -210,9 +306,16 @@ impl Bootloader {
     /// # Returns
     ///
     /// A `FlashStatusResponse` containing the current flash status
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`UnsupportedCommand`] error if the capabilities of the bootloader
+    /// exclude FLASH_STATUS
     pub async fn flash_status(&self, bllink: &mut Bllink) -> anyhow::Result<FlashStatusResponse> {
+        self.require(OptionalCommand::FlashStatus)?;
         let command = vec![0xff, self.target, CMD_FLASH_STATUS];
-        let response = bllink.request(&command, SHORT_TIMEOUT).await?;
+        let policy = self.policy(bllink, CommandClass::Query);
+        let response = bllink.request_with_policy(&command, &policy).await?;
         Ok(FlashStatusResponse::from_bytes(&response[2..]))
     }
 
@@ -239,7 +342,8 @@ impl Bootloader {
         command.extend_from_slice(&page.to_le_bytes());
         command.extend_from_slice(&address.to_le_bytes());
         
-        let response = bllink.request(&command, SHORT_TIMEOUT).await?;
+        let policy = self.policy(bllink, CommandClass::Query);
+        let response = bllink.request_with_policy(&command, &policy).await?;
         
         if response.len() < 2 {
             return Err(anyhow::anyhow!("Response too short: {} bytes", response.len()));
@@ -258,46 +362,89 @@ impl Bootloader {
         Ok(flash_packet)
     }
 
+    // Send GET_INFO until the bootloader answers or `timeout` expires
+    //
+    // Used to wait for a bootloader that is starting up.
+    pub(crate) async fn wait_for_info(&self, bllink: &mut Bllink, timeout: Duration) -> anyhow::Result<InfoPacket> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.get_info(bllink).await {
+                Ok(info) => return Ok(info),
+                Err(e) if Instant::now() >= deadline => return Err(e),
+                Err(_) => (),
+            }
+        }
+    }
+
     /// Initialize reset sequence (nRF51822 specific)
     ///
-    /// Prepares the bootloader for a system reset. This is typically called
+    /// Prepares the bootloader for a system reset. This must be called
     /// before [`reset`](Self::reset) to ensure a clean reset sequence.
     ///
     /// # Arguments
     ///
     /// * `bllink` - The Bllink interface to use for communication
-    pub async fn reset_init(&self, bllink: &mut Bllink) -> anyhow::Result<()> {
+    ///
+    /// # Returns
+    ///
+    /// The radio address the bootloader uses after a reset with [`ResetMode::Bootloader`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bootloader does not answer or the answer is too short
+    pub async fn reset_init(&self, bllink: &mut Bllink) -> anyhow::Result<[u8; 5]> {
         let command = vec![0xff, self.target, CMD_RESET_INIT];
-        bllink.send(&command).await?;
-        Ok(())
+        let policy = self.policy(bllink, CommandClass::Query);
+        let response = bllink.request_with_policy(&command, &policy).await?;
+
+        // [0xff, target, CMD_RESET_INIT, address (4 bytes, little endian), ...]
+        if response.len() < 7 {
+            return Err(anyhow::anyhow!(
+                "Invalid RESET_INIT response length: expected at least 7 bytes, got {}", response.len()));
+        }
+        Ok([0xb1, response[6], response[5], response[4], response[3]])
     }
 
     /// Reset the system
     ///
-    /// Triggers a system reset, restarting the Crazyflie into normal operation mode.
-    /// Call [`reset_init`](Self::reset_init) before this function.
+    /// Triggers a system reset, restarting the Crazyflie into its firmware or its bootloader
+    /// depending on `mode`. Call [`reset_init`](Self::reset_init) before this function.
+    ///
+    /// The command is sent once and is not checked for acknowledgement: the bootloader
+    /// restarts as soon as it receives it, so the ACK is often lost and sending the command
+    /// again would reach the restarted Crazyflie. Use [`CFLoader::reset`](crate::CFLoader::reset)
+    /// to confirm that the reset happened.
     ///
     /// # Arguments
     ///
     /// * `bllink` - The Bllink interface to use for communication
-    pub async fn reset(&self, bllink: &mut Bllink) -> anyhow::Result<()> {
-        let command = vec![0xff, self.target, CMD_RESET];
-        // No response expected for reset, but use request method
-        let _ = bllink.send(&command).await;
+    /// * `mode` - What the Crazyflie boots into after the reset
+    pub async fn reset(&self, bllink: &mut Bllink, mode: ResetMode) -> anyhow::Result<()> {
+        let command = vec![0xff, self.target, CMD_RESET, mode.byte()];
+        // One attempt lasting one poll interval sends exactly one packet
+        let policy = self.policy(bllink, CommandClass::Send);
+        let policy = policy.with_attempts(1).with_timeout(policy.poll_interval());
+        let _ = bllink.send_with_policy(&command, &policy).await;
         Ok(())
     }
 
     /// Turn off all systems (nRF51822 specific)
     ///
-    /// Shuts down all subsystems on the Crazyflie.
+    /// Shuts down all subsystems on the Crazyflie, including the nRF51822 itself. The
+    /// bootloader does not answer anymore once the command has been acknowledged.
     ///
     /// # Arguments
     ///
     /// * `bllink` - The Bllink interface to use for communication
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command is not acknowledged
     pub async fn all_off(&self, bllink: &mut Bllink) -> anyhow::Result<()> {
         let command = vec![0xff, self.target, CMD_ALLOFF];
-        // No response expected
-        let _ = bllink.send(&command).await;
+        // Only an ACK is expected
+        let policy = self.policy(bllink, CommandClass::Send);
+        bllink.send_with_policy(&command, &policy).await?;
         Ok(())
     }
 
@@ -308,10 +455,15 @@ impl Bootloader {
     /// # Arguments
     ///
     /// * `bllink` - The Bllink interface to use for communication
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command is not acknowledged
     pub async fn sys_off(&self, bllink: &mut Bllink) -> anyhow::Result<()> {
         let command = vec![0xff, self.target, CMD_SYSOFF];
-        // No response expected
-        let _ = bllink.send(&command).await;
+        // Only an ACK is expected
+        let policy = self.policy(bllink, CommandClass::Send);
+        bllink.send_with_policy(&command, &policy).await?;
         Ok(())
     }
 
@@ -322,10 +474,15 @@ impl Bootloader {
     /// # Arguments
     ///
     /// * `bllink` - The Bllink interface to use for communication
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command is not acknowledged
     pub async fn sys_on(&self, bllink: &mut Bllink) -> anyhow::Result<()> {
         let command = vec![0xff, self.target, CMD_SYSON];
-        // No response expected
-        let _ = bllink.send(&command).await;
+        // Only an ACK is expected
+        let policy = self.policy(bllink, CommandClass::Send);
+        bllink.send_with_policy(&command, &policy).await?;
         Ok(())
     }
 
@@ -339,21 +496,78 @@ impl Bootloader {
     ///
     /// # Returns
     ///
-    /// The battery voltage as a floating-point value in volts
+    /// A [`BatteryReading`] with the voltage and the estimated state of the power supply
     ///
     /// # Errors
     ///
-    /// Returns an error if the response length is invalid
-    pub async fn get_vbat(&self, bllink: &mut Bllink) -> anyhow::Result<f32> {
+    /// Returns an error if the response is too short or does not contain a valid voltage
+    pub async fn get_vbat(&self, bllink: &mut Bllink) -> anyhow::Result<BatteryReading> {
         let command = vec![0xff, self.target, CMD_GETVBAT];
-        let response = bllink.request(&command, SHORT_TIMEOUT).await?;
-        
-        if response.len() < 4 {
-            return Err(anyhow::anyhow!("Invalid VBAT response length"));
+        let policy = self.policy(bllink, CommandClass::Query);
+        let response = bllink.request_with_policy(&command, &policy).await?;
+
+        // [0xff, target, CMD_GETVBAT, vbat (f32, 4 bytes)]
+        if response.len() < 7 {
+            return Err(anyhow::anyhow!(
+                "Invalid VBAT response length: expected at least 7 bytes, got {}", response.len()));
         }
 
-        let vbat_bytes = [response[2], response[3], response[4], response[5]];
-        Ok(f32::from_le_bytes(vbat_bytes))
+        let voltage = f32::from_le_bytes([response[3], response[4], response[5], response[6]]);
+        if !voltage.is_finite() || voltage < 0.0 {
+            return Err(anyhow::anyhow!("Invalid VBAT value: {}", voltage));
+        }
+        Ok(BatteryReading::new(voltage))
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battery::BatteryState;
+    use crate::trace::{Trace, TraceEntry};
+
+    // Link answering GETVBAT with `payload` after the command echo
+    fn vbat_link(payload: &[u8]) -> Bllink {
+        let sent = vec![0xFF, TARGET_NRF51, CMD_GETVBAT];
+        let mut response = sent.clone();
+        response.extend_from_slice(payload);
+        Bllink::replay(Trace { entries: vec![TraceEntry { timestamp: Duration::ZERO, sent, acked: true, response }] })
+    }
+
+    #[tokio::test]
+    async fn reset_is_sent_once_without_ack() {
+        let sent = vec![0xFF, TARGET_NRF51, CMD_RESET, 0x01];
+        let unacked = TraceEntry { timestamp: Duration::ZERO, sent, acked: false, response: Vec::new() };
+        let mut bllink = Bllink::replay(Trace { entries: vec![unacked.clone(), unacked] });
+
+        Bootloader::nrf51().reset(&mut bllink, ResetMode::Firmware).await.unwrap();
+        assert_eq!(bllink.replay_remaining(), Some(1));
+        assert_eq!(bllink.stats().packets_sent, 1);
+    }
+
+    #[tokio::test]
+    async fn get_vbat_reads_the_voltage() {
+        let mut bllink = vbat_link(&3.7f32.to_le_bytes());
+        let reading = Bootloader::nrf51().get_vbat(&mut bllink).await.unwrap();
+        assert_eq!(reading.voltage, 3.7);
+        assert_eq!(reading.state, BatteryState::Normal);
+    }
+
+    #[tokio::test]
+    async fn get_vbat_rejects_a_short_answer() {
+        // 5 bytes: the command echo and 2 of the 4 voltage bytes
+        let mut bllink = vbat_link(&[0x00, 0x40]);
+        let error = Bootloader::nrf51().get_vbat(&mut bllink).await.unwrap_err();
+        assert!(error.to_string().contains("Invalid VBAT response length"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn get_vbat_rejects_invalid_voltages() {
+        for voltage in [f32::NAN, f32::INFINITY, -0.5] {
+            let mut bllink = vbat_link(&voltage.to_le_bytes());
+            let error = Bootloader::nrf51().get_vbat(&mut bllink).await.unwrap_err();
+            assert!(error.to_string().contains("Invalid VBAT value"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// Cancellation of long-running CFLoader operations
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Token used to cancel a long-running [`CFLoader`](crate::CFLoader) operation
+///
+/// The token is cheap to clone; all clones share the same state. Give one clone to the
+/// loader with [`CFLoader::set_cancellation_token`](crate::CFLoader::set_cancellation_token)
+/// and call [`cancel`](Self::cancel) on another one, for example from a GUI task.
+///
+/// Once cancelled, a token stays cancelled: use a new token for the next operation.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// use cfloader::{Bllink, CFLoader, CancellationToken};
+///
+/// let mut loader = CFLoader::new(Bllink::new(None).await?).await?;
+/// let token = CancellationToken::new();
+/// loader.set_cancellation_token(Some(token.clone()));
+///
+/// // From another task, when the user hits Cancel
+/// token.cancel();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a new token, not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation of the operations using this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Check if cancellation has been requested
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_cancellation() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!token.is_cancelled());
+
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert!(!CancellationToken::new().is_cancelled());
+    }
+}
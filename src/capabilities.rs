@@ -0,0 +1,220 @@
+// Capabilities of a bootloader, derived from its protocol version
+//
+// The protocol version is the last byte of the GET_INFO answer. Known versions
+// are those of the Crazyflie 1.0 bootloader (0x00 and 0x01) and of the
+// Crazyflie 2.x bootloaders (0x10). A newer version is assumed to keep the
+// commands of the Crazyflie 2.x protocol: commands are only refused when the
+// version is known not to support them.
+//
+// Version 0x00 is driven conservatively: it gets 16 bytes packets and no
+// FLASH_STATUS, the result of a WRITE_FLASH is only taken from its answer.
+
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::bootloader;
+use crate::packets::InfoPacket;
+
+// Payload of the buffer and flash packets of the first protocol version
+const CF1_V0_PAYLOAD: usize = 16;
+
+/// Bootloader command whose support depends on the protocol version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum OptionalCommand {
+    /// GET_MAPPING, flash sector mapping of the STM32
+    GetMapping,
+    /// SET_ADDRESS, radio address of the bootloader
+    SetAddress,
+    /// FLASH_STATUS, state of the last WRITE_FLASH
+    FlashStatus,
+}
+
+impl Display for OptionalCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OptionalCommand::GetMapping => write!(f, "GET_MAPPING"),
+            OptionalCommand::SetAddress => write!(f, "SET_ADDRESS"),
+            OptionalCommand::FlashStatus => write!(f, "FLASH_STATUS"),
+        }
+    }
+}
+
+/// What a bootloader supports, derived from its protocol version and target
+///
+/// Used by [`Bootloader`](crate::Bootloader) and [`CFLoader`](crate::CFLoader) to size the
+/// buffer and flash packets and to refuse the commands a bootloader does not implement.
+///
+/// # Example
+///
+/// ```
+/// use cfloader::{Capabilities, OptionalCommand, bootloader};
+///
+/// let stm32 = Capabilities::new(bootloader::TARGET_STM32, Capabilities::CF2_PROTOCOL);
+/// assert!(stm32.is_known());
+/// assert!(stm32.supports(OptionalCommand::GetMapping));
+/// assert_eq!(stm32.max_load_payload(), 25);
+///
+/// let nrf51 = Capabilities::new(bootloader::TARGET_NRF51, Capabilities::CF2_PROTOCOL);
+/// assert!(!nrf51.supports(OptionalCommand::GetMapping));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    target: u8,
+    version: u8,
+    known: bool,
+    max_load_payload: usize,
+    max_read_payload: usize,
+    get_mapping: bool,
+    set_address: bool,
+    flash_status: bool,
+}
+
+impl Capabilities {
+    /// Protocol version of the first Crazyflie 1.0 bootloader
+    pub const CF1_PROTOCOL_V0: u8 = 0x00;
+    /// Protocol version of the Crazyflie 1.0 bootloader with SET_ADDRESS
+    pub const CF1_PROTOCOL_V1: u8 = 0x01;
+    /// Protocol version of the Crazyflie 2.x bootloaders
+    pub const CF2_PROTOCOL: u8 = 0x10;
+
+    /// Capabilities of a bootloader target speaking a protocol version
+    pub fn new(target: u8, version: u8) -> Self {
+        let stm32 = target == bootloader::TARGET_STM32;
+        let (known, payload, get_mapping, set_address, flash_status) = match version {
+            Self::CF1_PROTOCOL_V0 => (true, CF1_V0_PAYLOAD, false, false, false),
+            Self::CF1_PROTOCOL_V1 => (true, bootloader::MAX_PAYLOAD, false, true, true),
+            // The STM32 has non-uniform flash sectors, the nRF51 owns the radio
+            Self::CF2_PROTOCOL => (true, bootloader::MAX_PAYLOAD, stm32, !stm32, true),
+            _ => (false, bootloader::MAX_PAYLOAD, true, true, true),
+        };
+        Capabilities {
+            target,
+            version,
+            known,
+            max_load_payload: payload,
+            max_read_payload: payload,
+            get_mapping,
+            set_address,
+            flash_status,
+        }
+    }
+
+    /// Capabilities of a bootloader target from its [`InfoPacket`]
+    pub fn from_info(target: u8, info: &InfoPacket) -> Self {
+        Self::new(target, info.version())
+    }
+
+    /// Get the bootloader target
+    pub fn target(&self) -> u8 {
+        self.target
+    }
+
+    /// Get the protocol version
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Check if the protocol version is one of the known versions
+    ///
+    /// Unknown versions get the packet sizes of the Crazyflie 2.x protocol and all commands.
+    pub fn is_known(&self) -> bool {
+        self.known
+    }
+
+    /// Maximum number of bytes of a LOAD_BUFFER command
+    pub fn max_load_payload(&self) -> usize {
+        self.max_load_payload
+    }
+
+    /// Maximum number of bytes used from a READ_FLASH or READ_BUFFER answer
+    pub fn max_read_payload(&self) -> usize {
+        self.max_read_payload
+    }
+
+    /// Check if the bootloader implements a command
+    pub fn supports(&self, command: OptionalCommand) -> bool {
+        match command {
+            OptionalCommand::GetMapping => self.get_mapping,
+            OptionalCommand::SetAddress => self.set_address,
+            OptionalCommand::FlashStatus => self.flash_status,
+        }
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let commands: Vec<String> = [OptionalCommand::GetMapping, OptionalCommand::SetAddress, OptionalCommand::FlashStatus]
+            .into_iter()
+            .filter(|command| self.supports(*command))
+            .map(|command| command.to_string())
+            .collect();
+        let commands = if commands.is_empty() { "none".to_string() } else { commands.join(", ") };
+        write!(f, "protocol 0x{:02X}{}, {} bytes loads, {} bytes reads, optional commands: {}",
+               self.version, if self.known { "" } else { " (unknown)" },
+               self.max_load_payload, self.max_read_payload, commands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootloader::{TARGET_NRF51, TARGET_STM32};
+
+    fn commands(target: u8, version: u8) -> (bool, bool, bool) {
+        let capabilities = Capabilities::new(target, version);
+        (capabilities.supports(OptionalCommand::GetMapping),
+         capabilities.supports(OptionalCommand::SetAddress),
+         capabilities.supports(OptionalCommand::FlashStatus))
+    }
+
+    fn payloads(target: u8, version: u8) -> (usize, usize) {
+        let capabilities = Capabilities::new(target, version);
+        (capabilities.max_load_payload(), capabilities.max_read_payload())
+    }
+
+    #[test]
+    fn cf1_v0_has_small_packets_and_no_optional_command() {
+        assert_eq!(commands(TARGET_STM32, Capabilities::CF1_PROTOCOL_V0), (false, false, false));
+        assert_eq!(commands(TARGET_NRF51, Capabilities::CF1_PROTOCOL_V0), (false, false, false));
+        assert_eq!(payloads(TARGET_STM32, Capabilities::CF1_PROTOCOL_V0), (16, 16));
+        assert!(Capabilities::new(TARGET_STM32, Capabilities::CF1_PROTOCOL_V0).is_known());
+    }
+
+    #[test]
+    fn cf1_v1_has_set_address_and_flash_status() {
+        assert_eq!(commands(TARGET_STM32, Capabilities::CF1_PROTOCOL_V1), (false, true, true));
+        assert_eq!(commands(TARGET_NRF51, Capabilities::CF1_PROTOCOL_V1), (false, true, true));
+        assert_eq!(payloads(TARGET_STM32, Capabilities::CF1_PROTOCOL_V1), (25, 25));
+    }
+
+    #[test]
+    fn cf2_commands_depend_on_the_target() {
+        assert_eq!(commands(TARGET_STM32, Capabilities::CF2_PROTOCOL), (true, false, true));
+        assert_eq!(commands(TARGET_NRF51, Capabilities::CF2_PROTOCOL), (false, true, true));
+        assert_eq!(payloads(TARGET_STM32, Capabilities::CF2_PROTOCOL), (25, 25));
+        assert_eq!(payloads(TARGET_NRF51, Capabilities::CF2_PROTOCOL), (25, 25));
+        assert!(Capabilities::new(TARGET_NRF51, Capabilities::CF2_PROTOCOL).is_known());
+    }
+
+    #[test]
+    fn unknown_versions_allow_all_commands() {
+        for version in [0x02, 0x11, 0xFF] {
+            let capabilities = Capabilities::new(TARGET_STM32, version);
+            assert!(!capabilities.is_known());
+            assert_eq!(commands(TARGET_STM32, version), (true, true, true));
+            assert_eq!(commands(TARGET_NRF51, version), (true, true, true));
+            assert_eq!(payloads(TARGET_NRF51, version), (25, 25));
+        }
+    }
+
+    #[test]
+    fn display_lists_packet_sizes_and_optional_commands() {
+        assert_eq!(Capabilities::new(TARGET_STM32, Capabilities::CF2_PROTOCOL).to_string(),
+                   "protocol 0x10, 25 bytes loads, 25 bytes reads, optional commands: GET_MAPPING, FLASH_STATUS");
+        assert_eq!(Capabilities::new(TARGET_STM32, Capabilities::CF1_PROTOCOL_V0).to_string(),
+                   "protocol 0x00, 16 bytes loads, 16 bytes reads, optional commands: none");
+        assert_eq!(Capabilities::new(TARGET_NRF51, 0x20).to_string(),
+                   "protocol 0x20 (unknown), 25 bytes loads, 25 bytes reads, optional commands: GET_MAPPING, SET_ADDRESS, FLASH_STATUS");
+    }
+}
@@ -2,9 +2,41 @@
 // Provide connectivity to both bootloader on the nRF and STM32
 // as well as high-level algorithm to program the Crazyflie 2.x
 
+use std::borrow::Cow;
+use std::io::BufReader;
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
 use crate::Bllink;
-use crate::bootloader::{self, Bootloader};
-use crate::packets::InfoPacket;
+use crate::battery::{BatteryGuard, BatteryMonitor, BatteryReading};
+use crate::bootloader::{self, Bootloader, ResetMode};
+use crate::cancel::CancellationToken;
+use crate::capabilities::Capabilities;
+use crate::diff::FlashDiff;
+use crate::digest::{FlashDigest, FlashHasher};
+use crate::error::{AbortReason, FlashWriteFailed, OperationAborted, OutOfBounds, ProtectedRegionViolation, VerificationFailed};
+use crate::identify::{self, FirmwareInventory, InstalledFirmware};
+use crate::layout::{FlashLayout, FlashPosition};
+use crate::packets::{FlashWriteStatus, InfoPacket};
+use crate::progress::{ProgressEvent, ProgressPhase, ProgressTracker};
+use crate::protect::{ProtectedRegion, SavedRegion};
+use crate::report::{Comparison, FlashReport, TargetUpdate, UpdateReport, VerifyReport};
+use crate::retry::RetryPolicies;
+use crate::sink::{AsyncWriteSink, ReadSink, WriteSink};
+use crate::stats::LinkStats;
+
+// Time given to the STM32 bootloader to answer after the STM32 is powered on
+const STM32_BOOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Time the STM32 is kept off when power cycled, so that its supply drops completely
+const STM32_POWER_CYCLE_OFF_TIME: Duration = Duration::from_millis(500);
+
+// Time given to the Crazyflie to leave or re-enter the bootloader after a reset
+const RESET_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Number of times a corrupted buffer slice is loaded again before giving up
+const BUFFER_RELOAD_ATTEMPTS: usize = 3;
 
 /// High-level interface for Crazyflie 2.x bootloader operations
 ///
@@ -22,9 +54,9 @@ use crate::packets::InfoPacket;
 /// let bllink = Bllink::new(None).await?;
 /// let mut loader = CFLoader::new(bllink).await?;
 ///
-/// // Flash firmware to STM32
+/// // Flash firmware to STM32, at the start of its firmware area
 /// let firmware = std::fs::read("firmware.bin")?;
-/// loader.flash_stm32(0x8000, &firmware).await?;
+/// loader.flash_firmware(cfloader::bootloader::TARGET_STM32, &firmware).await?;
 ///
 /// // Reset to normal operation
 /// loader.reset_to_firmware().await?;
@@ -37,6 +69,13 @@ pub struct CFLoader {
     stm32: Bootloader,
     nrf51_info: InfoPacket,
     stm32_info: InfoPacket,
+    cancellation_token: Option<CancellationToken>,
+    operation_timeout: Option<Duration>,
+    battery_guard: Option<BatteryGuard>,
+    buffer_readback: bool,
+    page_merge: bool,
+    protected_regions: Vec<ProtectedRegion>,
+    allow_protected_writes: bool,
 }
 
 impl CFLoader {
@@ -64,13 +103,60 @@ impl CFLoader {
         let nrf51_info = nrf51.get_info(&mut bllink).await?;
         let stm32_info = stm32.get_info(&mut bllink).await?;
         
-        Ok(CFLoader { 
-            bllink, 
-            nrf51, 
+        Ok(Self::from_parts(bllink, nrf51, stm32, nrf51_info, stm32_info))
+    }
+
+    /// Create a new CFLoader instance, power cycling the STM32 if it does not answer
+    ///
+    /// Same as [`new`](Self::new), except that if the STM32 bootloader does not answer
+    /// GET_INFO it is power cycled through the nRF51 and given another chance. This recovers
+    /// a wedged STM32 bootloader without having to touch the Crazyflie.
+    ///
+    /// # Arguments
+    ///
+    /// * `bllink` - An established Bllink connection to the Crazyflie bootloader
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nRF51 bootloader does not answer, or if the STM32 bootloader
+    /// still does not answer after the power cycle
+    pub async fn new_with_stm32_recovery(mut bllink: Bllink) -> anyhow::Result<Self> {
+        let nrf51 = Bootloader::new(bootloader::TARGET_NRF51);
+        let stm32 = Bootloader::new(bootloader::TARGET_STM32);
+
+        let nrf51_info = nrf51.get_info(&mut bllink).await?;
+        let stm32_info = match stm32.get_info(&mut bllink).await {
+            Ok(info) => info,
+            Err(_) => {
+                nrf51.sys_off(&mut bllink).await?;
+                tokio::time::sleep(STM32_POWER_CYCLE_OFF_TIME).await;
+                nrf51.sys_on(&mut bllink).await?;
+                stm32.wait_for_info(&mut bllink, STM32_BOOT_TIMEOUT).await
+                    .map_err(|e| e.context("STM32 bootloader not answering after power cycle"))?
+            }
+        };
+
+        Ok(Self::from_parts(bllink, nrf51, stm32, nrf51_info, stm32_info))
+    }
+
+    // Build the loader with the default settings once both bootloaders have answered
+    fn from_parts(bllink: Bllink, nrf51: Bootloader, stm32: Bootloader, nrf51_info: InfoPacket, stm32_info: InfoPacket) -> Self {
+        let mut loader = CFLoader {
+            bllink,
+            nrf51,
             stm32,
             nrf51_info,
             stm32_info,
-        })
+            cancellation_token: None,
+            operation_timeout: None,
+            battery_guard: None,
+            buffer_readback: false,
+            page_merge: true,
+            protected_regions: [ProtectedRegion::defaults(bootloader::TARGET_NRF51), ProtectedRegion::defaults(bootloader::TARGET_STM32)].concat(),
+            allow_protected_writes: false,
+        };
+        loader.update_capabilities();
+        loader
     }
 
     /// Get a formatted string with info from both bootloaders
@@ -97,6 +183,255 @@ impl CFLoader {
         &self.stm32_info
     }
 
+    /// Get the flash layout of the nRF51
+    pub fn nrf51_layout(&self) -> FlashLayout {
+        FlashLayout::from(&self.nrf51_info)
+    }
+
+    /// Get the flash layout of the STM32
+    pub fn stm32_layout(&self) -> FlashLayout {
+        FlashLayout::from(&self.stm32_info)
+    }
+
+    /// Get the flash layout of a target
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `target` is not a valid bootloader target
+    pub fn layout(&self, target: u8) -> anyhow::Result<FlashLayout> {
+        match target {
+            bootloader::TARGET_NRF51 => Ok(self.nrf51_layout()),
+            bootloader::TARGET_STM32 => Ok(self.stm32_layout()),
+            _ => Err(anyhow::anyhow!("Invalid bootloader target: 0x{:02X}", target)),
+        }
+    }
+
+    /// Get the capabilities of a target, derived from its protocol version
+    ///
+    /// Flash and read operations size their packets from these capabilities.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `target` is not a valid bootloader target
+    pub fn capabilities(&self, target: u8) -> anyhow::Result<Capabilities> {
+        match target {
+            bootloader::TARGET_NRF51 => Ok(Capabilities::from_info(target, &self.nrf51_info)),
+            bootloader::TARGET_STM32 => Ok(Capabilities::from_info(target, &self.stm32_info)),
+            _ => Err(anyhow::anyhow!("Invalid bootloader target: 0x{:02X}", target)),
+        }
+    }
+
+    // Let both bootloaders refuse what they do not support, after each GET_INFO
+    fn update_capabilities(&mut self) {
+        self.nrf51.set_capabilities(Some(Capabilities::from_info(bootloader::TARGET_NRF51, &self.nrf51_info)));
+        self.stm32.set_capabilities(Some(Capabilities::from_info(bootloader::TARGET_STM32, &self.stm32_info)));
+    }
+
+    /// Get the retry policies of the underlying link
+    pub fn retry_policies(&self) -> &RetryPolicies {
+        self.bllink.retry_policies()
+    }
+
+    /// Get a mutable reference to the retry policies of the underlying link
+    ///
+    /// The policies apply to both bootloaders and take effect on the next command.
+    pub fn retry_policies_mut(&mut self) -> &mut RetryPolicies {
+        self.bllink.retry_policies_mut()
+    }
+
+    /// Set the cancellation token checked by long-running operations
+    ///
+    /// Flash and read operations check the token at safe points: between two radio
+    /// commands, and never while a WRITE_FLASH command is in progress. When the token is
+    /// cancelled the operation returns an [`OperationAborted`] error reporting which pages
+    /// have been written.
+    ///
+    /// Passing `None` removes the token.
+    pub fn set_cancellation_token(&mut self, token: Option<CancellationToken>) {
+        self.cancellation_token = token;
+    }
+
+    /// Set the maximum duration of each long-running operation
+    ///
+    /// Flash and read operations taking longer than `timeout` are stopped at the next safe
+    /// point with an [`OperationAborted`] error, the same way as when cancelled.
+    ///
+    /// Passing `None` removes the limit.
+    pub fn set_operation_timeout(&mut self, timeout: Option<Duration>) {
+        self.operation_timeout = timeout;
+    }
+
+    /// Set the battery guard checked by flash operations
+    ///
+    /// With a guard set, flash operations read the battery voltage before writing the
+    /// first page and refuse to start if it is below the minimum of the guard. If the
+    /// guard rechecks the voltage, flash operations also stop before the next WRITE_FLASH
+    /// command when the voltage drops below the minimum. In both cases the operation
+    /// returns an [`OperationAborted`] error with [`AbortReason::LowBattery`].
+    ///
+    /// Passing `None` disables the check, which is the default.
+    pub fn set_battery_guard(&mut self, guard: Option<BatteryGuard>) {
+        self.battery_guard = guard;
+    }
+
+    /// Get the battery guard checked by flash operations
+    pub fn battery_guard(&self) -> Option<&BatteryGuard> {
+        self.battery_guard.as_ref()
+    }
+
+    /// Enable or disable the read-back of the RAM buffer before each flash write
+    ///
+    /// LOAD_BUFFER commands are only acknowledged, a packet corrupted on the way is
+    /// otherwise only discovered once the page is written. With read-back enabled, flash
+    /// operations read the loaded buffer back with READ_BUFFER before each WRITE_FLASH
+    /// command and load the 25 bytes slices that differ again. The number of reloaded
+    /// slices is reported in [`FlashReport::buffer_reloads`].
+    ///
+    /// This roughly doubles the radio traffic of a flash operation. Disabled by default.
+    pub fn set_buffer_readback(&mut self, enabled: bool) {
+        self.buffer_readback = enabled;
+    }
+
+    /// Whether the RAM buffer is read back before each flash write
+    pub fn buffer_readback(&self) -> bool {
+        self.buffer_readback
+    }
+
+    /// Enable or disable the merge of partially written pages
+    ///
+    /// The bootloaders only write whole pages. When an image does not start or end on a
+    /// page boundary, flash operations read the current content of the first and last
+    /// pages and write it back around the image, so that the bytes of these pages outside
+    /// of the image are kept.
+    ///
+    /// With merging disabled, flashing at an address that is not page-aligned fails and
+    /// the end of the last page is filled with 0xFF (erased). Enabled by default.
+    pub fn set_page_merge(&mut self, enabled: bool) {
+        self.page_merge = enabled;
+    }
+
+    /// Whether partially written pages are merged with their current content
+    pub fn page_merge(&self) -> bool {
+        self.page_merge
+    }
+
+    /// Get the flash regions that flash operations refuse to overwrite
+    ///
+    /// Initialized with [`ProtectedRegion::defaults`] of both targets.
+    pub fn protected_regions(&self) -> &[ProtectedRegion] {
+        &self.protected_regions
+    }
+
+    /// Get a mutable reference to the protected flash regions
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn example(loader: &mut cfloader::CFLoader) {
+    /// use cfloader::{ProtectedRegion, bootloader};
+    ///
+    /// // Keep the last 16 KB of the STM32 flash as well
+    /// let end = loader.stm32_layout().flash_end();
+    /// loader.protected_regions_mut().push(
+    ///     ProtectedRegion::new(bootloader::TARGET_STM32, end - 0x4000..end, "calibration"));
+    /// # }
+    /// ```
+    pub fn protected_regions_mut(&mut self) -> &mut Vec<ProtectedRegion> {
+        &mut self.protected_regions
+    }
+
+    /// Allow or refuse flash operations overwriting a protected region
+    ///
+    /// When refused, which is the default, a flash operation overlapping one of the
+    /// [`protected_regions`](Self::protected_regions) fails with a
+    /// [`ProtectedRegionViolation`] error before anything is written.
+    pub fn set_allow_protected_writes(&mut self, allow: bool) {
+        self.allow_protected_writes = allow;
+    }
+
+    /// Whether flash operations are allowed to overwrite protected regions
+    pub fn allow_protected_writes(&self) -> bool {
+        self.allow_protected_writes
+    }
+
+    // Refuse to write `addresses` of `target` if they overlap a protected region
+    fn check_protected(&self, target: u8, addresses: Range<u32>) -> anyhow::Result<()> {
+        if self.allow_protected_writes {
+            return Ok(());
+        }
+        match self.protected_regions.iter().find(|region| region.overlaps(target, &addresses)) {
+            Some(region) => Err(ProtectedRegionViolation { region: region.clone(), addresses }.into()),
+            None => Ok(()),
+        }
+    }
+
+    // Deadline of an operation starting now
+    fn operation_deadline(&self) -> Option<Instant> {
+        self.operation_timeout.map(|timeout| Instant::now() + timeout)
+    }
+
+    // Check if the current operation should be stopped
+    fn check_abort(&self, deadline: Option<Instant>) -> Option<AbortReason> {
+        if self.cancellation_token.as_ref().is_some_and(|token| token.is_cancelled()) {
+            Some(AbortReason::Cancelled)
+        } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            Some(AbortReason::DeadlineExceeded)
+        } else {
+            None
+        }
+    }
+
+    /// Read the battery voltage
+    ///
+    /// The voltage is measured by the nRF51, whichever target is being worked on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nRF51 bootloader does not answer or answers with an invalid voltage
+    pub async fn battery(&mut self) -> anyhow::Result<BatteryReading> {
+        self.nrf51.get_vbat(&mut self.bllink).await
+    }
+
+    /// Read the battery voltage periodically
+    ///
+    /// Returns a [`BatteryMonitor`] taking one reading every `period`. This is meant to
+    /// watch the battery while the Crazyflie is idle in bootloader mode, for example
+    /// while it charges before flashing.
+    pub fn monitor_battery(&mut self, period: Duration) -> BatteryMonitor<'_> {
+        BatteryMonitor::new(self, period)
+    }
+
+    // Check the battery voltage against the guard, if any
+    //
+    // `recheck` tells if this is a check between two flash writes, done only when the
+    // guard asks for it.
+    async fn check_battery(&mut self, recheck: bool) -> anyhow::Result<Option<AbortReason>> {
+        let Some(guard) = self.battery_guard else {
+            return Ok(None);
+        };
+        if recheck && !guard.recheck() {
+            return Ok(None);
+        }
+
+        let reading = self.battery().await
+            .map_err(|e| e.context("Cannot check battery voltage"))?;
+        if reading.voltage < guard.min_voltage() {
+            Ok(Some(AbortReason::LowBattery { voltage: reading.voltage, min_voltage: guard.min_voltage() }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Get a snapshot of the statistics of the underlying link
+    pub fn link_stats(&self) -> LinkStats {
+        self.bllink.stats()
+    }
+
+    /// Reset the statistics of the underlying link
+    pub fn reset_link_stats(&mut self) {
+        self.bllink.reset_stats();
+    }
+
     /// Get a detailed summary of both bootloaders
     pub fn get_bootloader_summary(&self) -> String {
         format!(
@@ -108,23 +443,27 @@ impl CFLoader {
             - Flash Pages: {}\n\
             - Flash Start: {}\n\
             - Version: 0x{:02X}\n\
+            - Capabilities: {}\n\
             \n\
             STM32F405 Bootloader:\n\
             - Page Size: {} bytes\n\
             - Buffer Pages: {}\n\
             - Flash Pages: {}\n\
             - Flash Start: {}\n\
-            - Version: 0x{:02X}",
+            - Version: 0x{:02X}\n\
+            - Capabilities: {}",
             self.nrf51_info.page_size(),
             self.nrf51_info.n_buff_page(),
             self.nrf51_info.n_flash_page(),
             self.nrf51_info.flash_start(),
             self.nrf51_info.version(),
+            Capabilities::from_info(bootloader::TARGET_NRF51, &self.nrf51_info),
             self.stm32_info.page_size(),
             self.stm32_info.n_buff_page(),
             self.stm32_info.n_flash_page(),
             self.stm32_info.flash_start(),
-            self.stm32_info.version()
+            self.stm32_info.version(),
+            Capabilities::from_info(bootloader::TARGET_STM32, &self.stm32_info),
         )
     }
 
@@ -134,40 +473,67 @@ impl CFLoader {
     /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
     /// * `start_address` - The starting address in flash where the image should be written
     /// * `image` - The image data to flash
-    /// * `progress_callback` - Optional callback function to report progress (bytes_written, total_bytes)
-    pub async fn flash_image_with_progress<F>(&mut self, target: u8, start_address: u32, image: &[u8], mut progress_callback: Option<F>) -> anyhow::Result<()> 
+    /// * `progress_callback` - Optional callback function called with a [`ProgressEvent`] after each
+    ///   buffer load and flash write
+    ///
+    /// # Returns
+    /// A [`FlashReport`] describing the operation, including the link statistics
+    ///
+    /// # Errors
+    /// Returns an [`OutOfBounds`] error, before anything is sent, if the image does not fit
+    /// in the firmware area. Returns an [`OperationAborted`] error if the operation is
+    /// cancelled or times out, see [`set_cancellation_token`](Self::set_cancellation_token)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn example(loader: &mut cfloader::CFLoader, firmware: &[u8]) -> anyhow::Result<()> {
+    /// use cfloader::{bootloader, ProgressEvent};
+    ///
+    /// loader.flash_image_with_progress(bootloader::TARGET_STM32, 0x4000, firmware, Some(|event: &ProgressEvent| {
+    ///     println!("{}", event);
+    /// })).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn flash_image_with_progress<F>(&mut self, target: u8, start_address: u32, image: &[u8], mut progress_callback: Option<F>) -> anyhow::Result<FlashReport> 
     where
-        F: FnMut(usize, usize),
+        F: FnMut(&ProgressEvent),
     {
         self.flash_image_internal(target, start_address, image, &mut progress_callback).await
     }
 
     /// Flash an image to either the nRF51 or STM32 bootloader
+    ///
+    /// Flash is written in whole pages, the content of the first and last pages outside
+    /// of the image is kept unless page merging is disabled, see
+    /// [`set_page_merge`](Self::set_page_merge).
     /// 
     /// # Arguments
     /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
     /// * `start_address` - The starting address in flash where the image should be written
     /// * `image` - The image data to flash
-    pub async fn flash_image(&mut self, target: u8, start_address: u32, image: &[u8]) -> anyhow::Result<()> {
-        self.flash_image_internal(target, start_address, image, &mut None::<fn(usize, usize)>).await
+    ///
+    /// # Returns
+    /// A [`FlashReport`] describing the operation, including the link statistics
+    pub async fn flash_image(&mut self, target: u8, start_address: u32, image: &[u8]) -> anyhow::Result<FlashReport> {
+        self.flash_image_internal(target, start_address, image, &mut None::<fn(&ProgressEvent)>).await
     }
 
     /// Internal flash implementation with optional progress callback
-    async fn flash_image_internal<F>(&mut self, target: u8, start_address: u32, image: &[u8], progress_callback: &mut Option<F>) -> anyhow::Result<()> 
+    async fn flash_image_internal<F>(&mut self, target: u8, start_address: u32, image: &[u8], progress_callback: &mut Option<F>) -> anyhow::Result<FlashReport> 
     where
-        F: FnMut(usize, usize),
+        F: FnMut(&ProgressEvent),
     {
         // Get the appropriate bootloader info
-        let (page_size, n_buff_pages, flash_start_page) = match target {
+        let (page_size, n_buff_pages) = match target {
             bootloader::TARGET_NRF51 => (
                 self.nrf51_info.page_size() as usize,
                 self.nrf51_info.n_buff_page() as usize,
-                self.nrf51_info.flash_start(),
             ),
             bootloader::TARGET_STM32 => (
                 self.stm32_info.page_size() as usize,
                 self.stm32_info.n_buff_page() as usize,
-                self.stm32_info.flash_start(),
             ),
             _ => return Err(anyhow::anyhow!("Invalid bootloader target: 0x{:02X}", target)),
         };
@@ -179,34 +545,73 @@ impl CFLoader {
         let start_page = (start_address / page_size as u32) as u16;
         
         // Validate that we're writing to a valid flash area
-        if start_page < flash_start_page {
-            return Err(anyhow::anyhow!(
-                "Cannot write to page {} (before flash start page {})", 
-                start_page, flash_start_page
-            ));
-        }
+        let layout = self.layout(target)?;
+        check_bounds(target, start_address, image.len(), layout.firmware_start()..layout.flash_end())?;
+
+        // Bytes outside of the image are only preserved when pages are merged
+        let padding = PagePadding::new(start_address, image.len(), page_size, self.page_merge)?;
+        self.check_protected(target, padding.overwritten(start_address, image.len()))?;
 
+        let start_time = Instant::now();
+        let deadline = self.operation_deadline();
+        let stats_before = self.bllink.stats();
 
+        // Whole pages are written, starting at the page holding the start address
+        let pages = self.align_to_pages(target, start_address, image, &padding).await?;
+
+        // Progress is counted in image bytes, like the report and aborted operations
+        let tracker = ProgressTracker::new(target, image.len(), stats_before.total_retries())
+            .skipping(padding.head);
+        let mut pages_written = 0u16;
         let mut bytes_written = 0;
-        let mut current_address = start_address;
+        let mut buffer_reloads = 0;
+        let mut current_address = start_address - padding.head as u32;
+
+        // Counters are in padded bytes, aborted operations report image bytes
+        let aborted = |reason, pages_written, bytes_done: usize| OperationAborted {
+            reason,
+            target,
+            written_pages: start_page..start_page + pages_written,
+            bytes_done: padding.image_bytes(bytes_done, image.len()),
+        };
 
+        // Never start writing with a low battery
+        if let Some(reason) = self.check_battery(false).await? {
+            return Err(aborted(reason, pages_written, bytes_written).into());
+        }
 
-        while bytes_written < image.len() {
+        while bytes_written < pages.len() {
             
             // Calculate how much data we can write in this iteration
-            let remaining_bytes = image.len() - bytes_written;
+            let remaining_bytes = pages.len() - bytes_written;
             let chunk_size = remaining_bytes.min(buffer_size);
-            let chunk = &image[bytes_written..bytes_written + chunk_size];
+            let chunk = &pages[bytes_written..bytes_written + chunk_size];
 
             // Calculate flash pages to write
             let current_page = (current_address / page_size as u32) as u16;
-            let pages_needed = ((chunk_size + page_size - 1) / page_size) as u16; // Round up
+            let pages_needed = chunk_size.div_ceil(page_size) as u16; // Round up
 
+            // Load the chunk into the buffer(s), this is a safe point to stop at
+            if let Some(reason) = self.load_chunk_to_buffer(target, chunk, page_size, deadline, &tracker, bytes_written, current_page, progress_callback).await? {
+                return Err(aborted(reason, pages_written, bytes_written).into());
+            }
 
+            // Make sure that what is about to be written is what has been sent
+            if self.buffer_readback
+                && let Some(reason) = self.check_buffer(target, chunk, page_size, deadline, &tracker, bytes_written + chunk_size, current_page,
+                                                        progress_callback, &mut buffer_reloads).await?
+            {
+                return Err(aborted(reason, pages_written, bytes_written).into());
+            }
+
+            // Last chance to stop before the flash is modified
+            if let Some(reason) = self.check_abort(deadline) {
+                return Err(aborted(reason, pages_written, bytes_written).into());
+            }
+            if pages_written > 0 && let Some(reason) = self.check_battery(true).await? {
+                return Err(aborted(reason, pages_written, bytes_written).into());
+            }
 
-            // Load the chunk into the buffer(s)
-            self.load_chunk_to_buffer(target, chunk, page_size).await?;
-            
             // Flash the buffer to flash memory
             let result = match target {
                 bootloader::TARGET_NRF51 => {
@@ -218,30 +623,79 @@ impl CFLoader {
                 _ => unreachable!(), // Already validated above
             };
 
-            // Check if the flash operation was successful
-            if !result.is_success() {
-                return Err(anyhow::anyhow!(
-                    "Flash operation failed at page {}: {}", 
-                    current_page, result.error()
-                ));
+            // Check if the flash operation was successful, unknown error codes are failures
+            let status = result.status();
+            if status != FlashWriteStatus::Done {
+                return Err(FlashWriteFailed { target, page: current_page, error: status.error() }.into());
             }
 
-
             // Update counters
+            pages_written += pages_needed;
             bytes_written += chunk_size;
             current_address += chunk_size as u32;
-            
-            // Call progress callback if provided
-            if let Some(callback) = progress_callback {
-                callback(bytes_written, image.len());
-            }
+
+            // Only reported once the pages are in flash, the last one completes the operation
+            self.report_progress(progress_callback, &tracker, ProgressPhase::WritingFlash, current_page, bytes_written);
         }
 
-        Ok(())
+        Ok(FlashReport {
+            target,
+            start_address,
+            bytes_written: image.len(),
+            first_page: start_page,
+            pages_written,
+            buffer_reloads,
+            duration: start_time.elapsed(),
+            link_stats: self.bllink.stats().since(&stats_before),
+        })
+    }
+
+    /// Extend an image to whole flash pages
+    ///
+    /// The bytes of the first and last pages outside of the image are read from flash when
+    /// `padding` merges pages, otherwise the last page is padded with 0xFF.
+    async fn align_to_pages<'a>(&mut self, target: u8, start_address: u32, image: &'a [u8], padding: &PagePadding) -> anyhow::Result<Cow<'a, [u8]>> {
+        if padding.is_aligned() {
+            return Ok(Cow::Borrowed(image));
+        }
+
+        let head = if padding.head > 0 {
+            self.read_existing(target, start_address - padding.head as u32, padding.head).await?
+        } else {
+            Vec::new()
+        };
+        let tail = if padding.tail > 0 && padding.merge {
+            self.read_existing(target, start_address + image.len() as u32, padding.tail).await?
+        } else {
+            Vec::new()
+        };
+
+        Ok(padding.apply(&head, image, &tail))
+    }
+
+    // Read flash content to be written back, which must be read completely
+    async fn read_existing(&mut self, target: u8, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+        let data = self.read_flash_internal(target, address, length as u32, ProgressPhase::Reading, &mut None::<fn(&ProgressEvent)>).await
+            .map_err(|e| e.context(format!("Cannot read the content of the page at 0x{:08X} to merge it", address)))?;
+        if data.len() != length {
+            return Err(anyhow::anyhow!("Flash read stopped after {} of {} bytes at 0x{:08X}", data.len(), length, address));
+        }
+        Ok(data)
     }
 
     /// Load a chunk of data into the bootloader's buffer pages
-    async fn load_chunk_to_buffer(&mut self, target: u8, chunk: &[u8], page_size: usize) -> anyhow::Result<()> {
+    ///
+    /// `bytes_before` and `flash_page` locate the chunk in the image and in flash for progress reporting.
+    ///
+    /// Returns the reason if the operation has been stopped before the chunk is fully loaded.
+    #[allow(clippy::too_many_arguments)]
+    async fn load_chunk_to_buffer<F>(&mut self, target: u8, chunk: &[u8], page_size: usize, deadline: Option<Instant>,
+                                     tracker: &ProgressTracker, bytes_before: usize, flash_page: u16,
+                                     progress_callback: &mut Option<F>) -> anyhow::Result<Option<AbortReason>>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let max_load_size = self.capabilities(target)?.max_load_payload();
         let mut chunk_offset = 0;
         let mut buffer_page = 0u16;
 
@@ -254,13 +708,16 @@ impl CFLoader {
             let mut bytes_written_to_page = 0;
 
             while bytes_written_to_page < bytes_to_write {
-                // Calculate how much we can write in this load_buffer call (max 25 bytes per call)
+                if let Some(reason) = self.check_abort(deadline) {
+                    return Ok(Some(reason));
+                }
+
+                // Calculate how much we can write in this load_buffer call, as allowed by the protocol version
                 let remaining_in_page = bytes_to_write - bytes_written_to_page;
-                let load_size = remaining_in_page.min(25); // reduced from 27 to 25 due to missing last 2 bytes
+                let load_size = remaining_in_page.min(max_load_size);
                 
                 let data_slice = &chunk[chunk_offset + bytes_written_to_page..chunk_offset + bytes_written_to_page + load_size];
-                let _global_offset = chunk_offset + bytes_written_to_page;
-                
+
                 match target {
                     bootloader::TARGET_NRF51 => {
                         self.nrf51.load_buffer(&mut self.bllink, buffer_page, page_offset, data_slice).await?;
@@ -273,13 +730,89 @@ impl CFLoader {
                 
                 page_offset += load_size as u16;
                 bytes_written_to_page += load_size;
+
+                self.report_progress(progress_callback, tracker, ProgressPhase::LoadingBuffer,
+                                     flash_page + buffer_page, bytes_before + chunk_offset + bytes_written_to_page);
             }
 
             chunk_offset += bytes_to_write;
             buffer_page += 1;
         }
 
-        Ok(())
+        Ok(None)
+    }
+
+    /// Read back a loaded chunk and load again the slices that do not match
+    ///
+    /// The buffer is read in slices fitting both a LOAD_BUFFER and a READ_BUFFER packet. `reloads` is
+    /// incremented for each slice loaded again. Progress events keep `bytes_loaded`, the bytes done
+    /// once the chunk has been loaded, so that the progress does not go back while checking.
+    ///
+    /// Returns the reason if the operation has been stopped before the chunk is fully checked,
+    /// and an error if a slice is still corrupted after [`BUFFER_RELOAD_ATTEMPTS`] reloads.
+    #[allow(clippy::too_many_arguments)]
+    async fn check_buffer<F>(&mut self, target: u8, chunk: &[u8], page_size: usize, deadline: Option<Instant>,
+                             tracker: &ProgressTracker, bytes_loaded: usize, flash_page: u16,
+                             progress_callback: &mut Option<F>, reloads: &mut usize) -> anyhow::Result<Option<AbortReason>>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let capabilities = self.capabilities(target)?;
+        let slice_size = capabilities.max_load_payload().min(capabilities.max_read_payload());
+
+        for (page_index, page) in chunk.chunks(page_size).enumerate() {
+            let buffer_page = page_index as u16;
+
+            for (slice_index, expected) in page.chunks(slice_size).enumerate() {
+                let page_offset = (slice_index * slice_size) as u16;
+                let mut attempts = 0;
+
+                loop {
+                    if let Some(reason) = self.check_abort(deadline) {
+                        return Ok(Some(reason));
+                    }
+
+                    let loaded = match target {
+                        bootloader::TARGET_NRF51 => self.nrf51.read_buffer(&mut self.bllink, buffer_page, page_offset).await?,
+                        bootloader::TARGET_STM32 => self.stm32.read_buffer(&mut self.bllink, buffer_page, page_offset).await?,
+                        _ => return Err(anyhow::anyhow!("Invalid bootloader target: 0x{:02X}", target)),
+                    };
+                    if loaded.data.get(..expected.len()) == Some(expected) {
+                        break;
+                    }
+
+                    if attempts == BUFFER_RELOAD_ATTEMPTS {
+                        return Err(anyhow::anyhow!(
+                            "Buffer page {} offset {} still corrupted after {} reloads",
+                            buffer_page, page_offset, attempts
+                        ));
+                    }
+                    attempts += 1;
+                    *reloads += 1;
+
+                    match target {
+                        bootloader::TARGET_NRF51 => self.nrf51.load_buffer(&mut self.bllink, buffer_page, page_offset, expected).await?,
+                        bootloader::TARGET_STM32 => self.stm32.load_buffer(&mut self.bllink, buffer_page, page_offset, expected).await?,
+                        _ => unreachable!(), // Already validated by the read
+                    }
+                }
+
+                self.report_progress(progress_callback, tracker, ProgressPhase::CheckingBuffer,
+                                     flash_page + buffer_page, bytes_loaded);
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Call the progress callback, if any, with the current state of the operation
+    fn report_progress<F>(&self, progress_callback: &mut Option<F>, tracker: &ProgressTracker, phase: ProgressPhase, page: u16, bytes_done: usize)
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        if let Some(callback) = progress_callback {
+            callback(&tracker.event(phase, page, bytes_done, self.bllink.total_retries()));
+        }
     }
 
     /// Flash an image to the STM32 bootloader with progress callback
@@ -291,10 +824,10 @@ impl CFLoader {
     ///
     /// * `start_address` - The starting address in flash where the image should be written
     /// * `image` - The image data to flash
-    /// * `progress_callback` - Optional callback function to report progress (bytes_written, total_bytes)
-    pub async fn flash_stm32_with_progress<F>(&mut self, start_address: u32, image: &[u8], progress_callback: Option<F>) -> anyhow::Result<()> 
+    /// * `progress_callback` - Optional callback function called with a [`ProgressEvent`]
+    pub async fn flash_stm32_with_progress<F>(&mut self, start_address: u32, image: &[u8], progress_callback: Option<F>) -> anyhow::Result<FlashReport> 
     where
-        F: FnMut(usize, usize),
+        F: FnMut(&ProgressEvent),
     {
         self.flash_image_with_progress(bootloader::TARGET_STM32, start_address, image, progress_callback).await
     }
@@ -308,10 +841,10 @@ impl CFLoader {
     ///
     /// * `start_address` - The starting address in flash where the image should be written
     /// * `image` - The image data to flash
-    /// * `progress_callback` - Optional callback function to report progress (bytes_written, total_bytes)
-    pub async fn flash_nrf51_with_progress<F>(&mut self, start_address: u32, image: &[u8], progress_callback: Option<F>) -> anyhow::Result<()> 
+    /// * `progress_callback` - Optional callback function called with a [`ProgressEvent`]
+    pub async fn flash_nrf51_with_progress<F>(&mut self, start_address: u32, image: &[u8], progress_callback: Option<F>) -> anyhow::Result<FlashReport> 
     where
-        F: FnMut(usize, usize),
+        F: FnMut(&ProgressEvent),
     {
         self.flash_image_with_progress(bootloader::TARGET_NRF51, start_address, image, progress_callback).await
     }
@@ -324,7 +857,7 @@ impl CFLoader {
     ///
     /// * `start_address` - The starting address in flash where the image should be written
     /// * `image` - The image data to flash
-    pub async fn flash_stm32(&mut self, start_address: u32, image: &[u8]) -> anyhow::Result<()> {
+    pub async fn flash_stm32(&mut self, start_address: u32, image: &[u8]) -> anyhow::Result<FlashReport> {
         self.flash_image(bootloader::TARGET_STM32, start_address, image).await
     }
 
@@ -336,11 +869,15 @@ impl CFLoader {
     ///
     /// * `start_address` - The starting address in flash where the image should be written
     /// * `image` - The image data to flash
-    pub async fn flash_nrf51(&mut self, start_address: u32, image: &[u8]) -> anyhow::Result<()> {
+    pub async fn flash_nrf51(&mut self, start_address: u32, image: &[u8]) -> anyhow::Result<FlashReport> {
         self.flash_image(bootloader::TARGET_NRF51, start_address, image).await
     }
 
     /// Read flash content from either the nRF51 or STM32 bootloader
+    ///
+    /// The whole content is collected in memory, use
+    /// [`read_flash_to_writer`](Self::read_flash_to_writer) or
+    /// [`read_flash_to_async_writer`](Self::read_flash_to_async_writer) for large dumps.
     /// 
     /// # Arguments
     /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
@@ -349,109 +886,1473 @@ impl CFLoader {
     /// 
     /// # Returns
     /// A `Vec<u8>` containing the read flash content
+    ///
+    /// # Errors
+    /// Returns an [`OutOfBounds`] error, before anything is sent, if the range goes past
+    /// the end of the flash. Returns an [`OperationAborted`] error if the operation is
+    /// cancelled or times out, see [`set_cancellation_token`](Self::set_cancellation_token)
     pub async fn read_flash(&mut self, target: u8, start_address: u32, length: u32) -> anyhow::Result<Vec<u8>> {
-        // Get the appropriate bootloader info
-        let page_size = match target {
-            bootloader::TARGET_NRF51 => self.nrf51_info.page_size() as usize,
-            bootloader::TARGET_STM32 => self.stm32_info.page_size() as usize,
-            _ => return Err(anyhow::anyhow!("Invalid bootloader target: 0x{:02X}", target)),
-        };
-
-
-        let mut result = Vec::with_capacity(length as usize);
-        let mut bytes_read = 0u32;
-        let mut current_address = start_address;
-
-        // The bootloader can read up to 27 bytes per read_flash call (based on protocol limit)
-        const MAX_READ_SIZE: usize = 27;
-
-        while bytes_read < length {
-            let remaining_bytes = length - bytes_read;
-            let read_size = (remaining_bytes as usize).min(MAX_READ_SIZE);
-
-            // Calculate page and offset within page
-            let current_page = (current_address / page_size as u32) as u16;
-            let page_offset = (current_address % page_size as u32) as u16;
-
-            // Read from flash
-            let flash_data = match target {
-                bootloader::TARGET_NRF51 => {
-                    self.nrf51.read_flash(&mut self.bllink, current_page, page_offset).await?
-                },
-                bootloader::TARGET_STM32 => {
-                    self.stm32.read_flash(&mut self.bllink, current_page, page_offset).await?
-                },
-                _ => unreachable!(), // Already validated above
-            };
-
-            // Take only the bytes we need (the response might contain more data than requested)
-            let data_to_take = read_size.min(flash_data.data.len());
-            
-            if data_to_take == 0 {
-                break;
-            }
-            
-            result.extend_from_slice(&flash_data.data[..data_to_take]);
-
-            bytes_read += data_to_take as u32;
-            current_address += data_to_take as u32;
-        }
-
-        Ok(result)
+        self.read_flash_internal(target, start_address, length, ProgressPhase::Reading, &mut None::<fn(&ProgressEvent)>).await
     }
 
-    /// Read flash content from the STM32 bootloader
-    ///
-    /// Convenience method that wraps [`read_flash`](Self::read_flash) for the STM32 target.
+    /// Read flash content from either the nRF51 or STM32 bootloader with progress callback
     ///
     /// # Arguments
-    ///
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
     /// * `start_address` - The starting address in flash to read from
     /// * `length` - The number of bytes to read
+    /// * `progress_callback` - Optional callback function called with a [`ProgressEvent`] after each read
     ///
     /// # Returns
-    ///
     /// A `Vec<u8>` containing the read flash content
-    pub async fn read_stm32_flash(&mut self, start_address: u32, length: u32) -> anyhow::Result<Vec<u8>> {
-        self.read_flash(bootloader::TARGET_STM32, start_address, length).await
+    ///
+    /// # Errors
+    /// Returns an [`OutOfBounds`] error, before anything is sent, if the range goes past
+    /// the end of the flash. Returns an [`OperationAborted`] error if the operation is
+    /// cancelled or times out, see [`set_cancellation_token`](Self::set_cancellation_token)
+    pub async fn read_flash_with_progress<F>(&mut self, target: u8, start_address: u32, length: u32, mut progress_callback: Option<F>) -> anyhow::Result<Vec<u8>>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        self.read_flash_internal(target, start_address, length, ProgressPhase::Reading, &mut progress_callback).await
     }
 
-    /// Read flash content from the nRF51 bootloader
+    /// Read flash content into a writer
     ///
-    /// Convenience method that wraps [`read_flash`](Self::read_flash) for the nRF51 target.
+    /// Same as [`read_flash`](Self::read_flash), except that the data is written to `writer`
+    /// as it arrives instead of being collected in memory. The writer is flushed at the end.
     ///
     /// # Arguments
-    ///
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
     /// * `start_address` - The starting address in flash to read from
     /// * `length` - The number of bytes to read
+    /// * `writer` - Destination of the data
     ///
     /// # Returns
+    /// The number of bytes read and written
     ///
-    /// A `Vec<u8>` containing the read flash content
+    /// # Errors
+    /// Returns an error if the flash cannot be read or if writing fails. The data read before
+    /// the error has been written.
+    pub async fn read_flash_to_writer<W>(&mut self, target: u8, start_address: u32, length: u32, writer: &mut W) -> anyhow::Result<u32>
+    where
+        W: std::io::Write,
+    {
+        self.read_flash_to_writer_with_progress(target, start_address, length, writer, None::<fn(&ProgressEvent)>).await
+    }
+
+    /// Read flash content into a writer with progress callback
+    ///
+    /// Same as [`read_flash_to_writer`](Self::read_flash_to_writer), reporting
+    /// [`ProgressPhase::Reading`] events.
+    pub async fn read_flash_to_writer_with_progress<W, F>(&mut self, target: u8, start_address: u32, length: u32, writer: &mut W, mut progress_callback: Option<F>) -> anyhow::Result<u32>
+    where
+        W: std::io::Write,
+        F: FnMut(&ProgressEvent),
+    {
+        let bytes_read = self.read_flash_to_sink(target, start_address, length, ProgressPhase::Reading, &mut progress_callback, &mut WriteSink(writer)).await?;
+        writer.flush()?;
+        Ok(bytes_read)
+    }
+
+    /// Read flash content into an async writer
+    ///
+    /// Same as [`read_flash_to_writer`](Self::read_flash_to_writer) for an async writer such
+    /// as a [`tokio::fs::File`] or a [`tokio::net::TcpStream`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn example(loader: &mut cfloader::CFLoader) -> anyhow::Result<()> {
+    /// use cfloader::bootloader;
+    ///
+    /// let layout = loader.stm32_layout();
+    /// let mut file = tokio::fs::File::create("stm32_dump.bin").await?;
+    /// loader.read_flash_to_async_writer(bootloader::TARGET_STM32, layout.firmware_start(),
+    ///                                   layout.firmware_size(), &mut file).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn read_flash_to_async_writer<W>(&mut self, target: u8, start_address: u32, length: u32, writer: &mut W) -> anyhow::Result<u32>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        self.read_flash_to_async_writer_with_progress(target, start_address, length, writer, None::<fn(&ProgressEvent)>).await
+    }
+
+    /// Read flash content into an async writer with progress callback
+    ///
+    /// Same as [`read_flash_to_async_writer`](Self::read_flash_to_async_writer), reporting
+    /// [`ProgressPhase::Reading`] events.
+    pub async fn read_flash_to_async_writer_with_progress<W, F>(&mut self, target: u8, start_address: u32, length: u32, writer: &mut W, mut progress_callback: Option<F>) -> anyhow::Result<u32>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+        F: FnMut(&ProgressEvent),
+    {
+        let bytes_read = self.read_flash_to_sink(target, start_address, length, ProgressPhase::Reading, &mut progress_callback, &mut AsyncWriteSink(writer)).await?;
+        tokio::io::AsyncWriteExt::flush(writer).await?;
+        Ok(bytes_read)
+    }
+
+    /// Internal read implementation with optional progress callback
+    ///
+    /// `phase` is the phase reported in progress events.
+    async fn read_flash_internal<F>(&mut self, target: u8, start_address: u32, length: u32, phase: ProgressPhase, progress_callback: &mut Option<F>) -> anyhow::Result<Vec<u8>>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let mut result = Vec::with_capacity(length as usize);
+        self.read_flash_to_sink(target, start_address, length, phase, progress_callback, &mut result).await?;
+        Ok(result)
+    }
+
+    /// Read flash content into a sink, chunk by chunk
+    ///
+    /// Returns the number of bytes read, which is less than `length` if the bootloader
+    /// stops returning data.
+    async fn read_flash_to_sink<F, S>(&mut self, target: u8, start_address: u32, length: u32, phase: ProgressPhase,
+                                      progress_callback: &mut Option<F>, sink: &mut S) -> anyhow::Result<u32>
+    where
+        F: FnMut(&ProgressEvent),
+        S: ReadSink,
+    {
+        // Get the appropriate bootloader info
+        let page_size = match target {
+            bootloader::TARGET_NRF51 => self.nrf51_info.page_size() as usize,
+            bootloader::TARGET_STM32 => self.stm32_info.page_size() as usize,
+            _ => return Err(anyhow::anyhow!("Invalid bootloader target: 0x{:02X}", target)),
+        };
+        check_bounds(target, start_address, length as usize, 0..self.layout(target)?.flash_end())?;
+
+        let deadline = self.operation_deadline();
+        let tracker = ProgressTracker::new(target, length as usize, self.bllink.total_retries());
+        let mut bytes_read = 0u32;
+        let mut current_address = start_address;
+
+        // The bootloader returns a limited number of bytes per read_flash call, depending on its protocol version
+        let max_read_size = self.capabilities(target)?.max_read_payload();
+
+        while bytes_read < length {
+            if let Some(reason) = self.check_abort(deadline) {
+                return Err(OperationAborted {
+                    reason,
+                    target,
+                    written_pages: 0..0,
+                    bytes_done: bytes_read as usize,
+                }.into());
+            }
+
+            let remaining_bytes = length - bytes_read;
+            let read_size = (remaining_bytes as usize).min(max_read_size);
+
+            // Calculate page and offset within page
+            let current_page = (current_address / page_size as u32) as u16;
+            let page_offset = (current_address % page_size as u32) as u16;
+
+            // Read from flash
+            let flash_data = match target {
+                bootloader::TARGET_NRF51 => {
+                    self.nrf51.read_flash(&mut self.bllink, current_page, page_offset).await?
+                },
+                bootloader::TARGET_STM32 => {
+                    self.stm32.read_flash(&mut self.bllink, current_page, page_offset).await?
+                },
+                _ => unreachable!(), // Already validated above
+            };
+
+            // Take only the bytes we need (the response might contain more data than requested)
+            let data_to_take = read_size.min(flash_data.data.len());
+            
+            if data_to_take == 0 {
+                break;
+            }
+            
+            sink.write_chunk(&flash_data.data[..data_to_take]).await?;
+
+            bytes_read += data_to_take as u32;
+            current_address += data_to_take as u32;
+
+            self.report_progress(progress_callback, &tracker, phase, current_page, bytes_read as usize);
+        }
+
+        Ok(bytes_read)
+    }
+
+    /// Compare flash content with an image
+    ///
+    /// Reads back the flash where `image` is expected and compares it byte by byte.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    /// * `start_address` - The address in flash where the image is expected
+    /// * `image` - The expected image data
+    ///
+    /// # Returns
+    /// A [`VerifyReport`] describing the differences, if any
+    ///
+    /// # Errors
+    /// Returns an error if flash cannot be read. A mismatch is not an error, see [`VerifyReport::is_match`].
+    pub async fn verify_image(&mut self, target: u8, start_address: u32, image: &[u8]) -> anyhow::Result<VerifyReport> {
+        self.verify_image_internal(target, start_address, image, &mut None::<fn(&ProgressEvent)>).await
+    }
+
+    /// Compare flash content with an image with progress callback
+    ///
+    /// Same as [`verify_image`](Self::verify_image), reporting [`ProgressPhase::Verifying`] events.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    /// * `start_address` - The address in flash where the image is expected
+    /// * `image` - The expected image data
+    /// * `progress_callback` - Optional callback function called with a [`ProgressEvent`] after each read
+    pub async fn verify_image_with_progress<F>(&mut self, target: u8, start_address: u32, image: &[u8], mut progress_callback: Option<F>) -> anyhow::Result<VerifyReport>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        self.verify_image_internal(target, start_address, image, &mut progress_callback).await
+    }
+
+    /// Internal verify implementation with optional progress callback
+    async fn verify_image_internal<F>(&mut self, target: u8, start_address: u32, image: &[u8], progress_callback: &mut Option<F>) -> anyhow::Result<VerifyReport>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let start_time = Instant::now();
+        let stats_before = self.bllink.stats();
+
+        let length = image.len() as u32;
+        let mut comparison = Comparison::new(start_address, image);
+        self.read_flash_to_sink(target, start_address, length, ProgressPhase::Verifying, progress_callback, &mut comparison).await?;
+        let (mismatched_bytes, mismatched_ranges) = comparison.finish(start_address + length);
+
+        Ok(VerifyReport {
+            target,
+            start_address,
+            length: image.len(),
+            mismatched_bytes,
+            first_mismatch: mismatched_ranges.first().map(|range| range.start),
+            mismatched_ranges,
+            duration: start_time.elapsed(),
+            link_stats: self.bllink.stats().since(&stats_before),
+        })
+    }
+
+    /// Compare flash content with an image file
+    ///
+    /// Same as [`verify_image`](Self::verify_image), except that the file is read as the
+    /// flash is read instead of being loaded in memory.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    /// * `start_address` - The address in flash where the image is expected
+    /// * `path` - Path of the expected image file
+    ///
+    /// # Errors
+    /// Returns an error if the file or the flash cannot be read. A mismatch is not an error,
+    /// see [`VerifyReport::is_match`].
+    pub async fn compare_with_file(&mut self, target: u8, start_address: u32, path: impl AsRef<Path>) -> anyhow::Result<VerifyReport> {
+        let start_time = Instant::now();
+        let stats_before = self.bllink.stats();
+
+        let file = std::fs::File::open(path.as_ref())
+            .map_err(|e| anyhow::anyhow!("Cannot open image file {}: {}", path.as_ref().display(), e))?;
+        let length = u32::try_from(file.metadata()?.len())
+            .map_err(|_| anyhow::anyhow!("Image file {} is too large", path.as_ref().display()))?;
+
+        let mut comparison = Comparison::new(start_address, BufReader::new(file));
+        self.read_flash_to_sink(target, start_address, length, ProgressPhase::Verifying, &mut None::<fn(&ProgressEvent)>, &mut comparison).await?;
+        let (mismatched_bytes, mismatched_ranges) = comparison.finish(start_address + length);
+
+        Ok(VerifyReport {
+            target,
+            start_address,
+            length: length as usize,
+            mismatched_bytes,
+            first_mismatch: mismatched_ranges.first().map(|range| range.start),
+            mismatched_ranges,
+            duration: start_time.elapsed(),
+            link_stats: self.bllink.stats().since(&stats_before),
+        })
+    }
+
+    /// Compare flash content with an image, page by page
+    ///
+    /// Unlike [`verify_image`](Self::verify_image), the result groups the differences in
+    /// ranges of flash pages, classifies them as erased or programmed and keeps a hexdump
+    /// of the start of each range.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    /// * `start_address` - The address in flash where the image is expected
+    /// * `image` - The expected image data
+    ///
+    /// # Returns
+    /// A [`FlashDiff`] describing the differences, if any
+    ///
+    /// # Errors
+    /// Returns an error if the flash cannot be read completely. A mismatch is not an error,
+    /// see [`FlashDiff::is_match`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn example(loader: &mut cfloader::CFLoader, firmware: &[u8]) -> anyhow::Result<()> {
+    /// use cfloader::bootloader;
+    ///
+    /// let start = loader.stm32_layout().firmware_start();
+    /// let diff = loader.diff_image(bootloader::TARGET_STM32, start, firmware).await?;
+    /// for range in &diff.ranges {
+    ///     println!("{}", range);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn diff_image(&mut self, target: u8, start_address: u32, image: &[u8]) -> anyhow::Result<FlashDiff> {
+        self.diff_image_internal(target, start_address, image, &mut None::<fn(&ProgressEvent)>).await
+    }
+
+    /// Compare flash content with an image, page by page, with progress callback
+    ///
+    /// Same as [`diff_image`](Self::diff_image), reporting [`ProgressPhase::Verifying`] events.
+    pub async fn diff_image_with_progress<F>(&mut self, target: u8, start_address: u32, image: &[u8], mut progress_callback: Option<F>) -> anyhow::Result<FlashDiff>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        self.diff_image_internal(target, start_address, image, &mut progress_callback).await
+    }
+
+    /// Internal diff implementation with optional progress callback
+    async fn diff_image_internal<F>(&mut self, target: u8, start_address: u32, image: &[u8], progress_callback: &mut Option<F>) -> anyhow::Result<FlashDiff>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let page_size = self.layout(target)?.page_size();
+        let flash = self.read_flash_internal(target, start_address, image.len() as u32, ProgressPhase::Verifying, progress_callback).await?;
+        if flash.len() != image.len() {
+            return Err(anyhow::anyhow!("Flash read stopped after {} of {} bytes", flash.len(), image.len()));
+        }
+        FlashDiff::new(target, page_size, start_address, &flash, image)
+    }
+
+    /// Save the content of the protected regions
+    ///
+    /// Reads every protected region, clipped to the flash of its target, so that it can be
+    /// written back with [`restore_protected_regions`](Self::restore_protected_regions)
+    /// after an operation overwriting it.
+    ///
+    /// # Errors
+    /// Returns an error if a region cannot be read completely
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn example(loader: &mut cfloader::CFLoader, backup: &[u8]) -> anyhow::Result<()> {
+    /// use cfloader::bootloader;
+    ///
+    /// // Erase the whole firmware area, then put the protected content back
+    /// let saved = loader.save_protected_regions().await?;
+    /// loader.set_allow_protected_writes(true);
+    /// let result = loader.erase_firmware(bootloader::TARGET_STM32).await;
+    /// loader.restore_protected_regions(&saved).await?;
+    /// loader.set_allow_protected_writes(false);
+    /// result?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// To write a firmware image while keeping the protected content, see
+    /// [`restore_firmware`](Self::restore_firmware).
+    pub async fn save_protected_regions(&mut self) -> anyhow::Result<Vec<SavedRegion>> {
+        let regions = self.protected_regions.clone();
+        self.save_regions(regions).await
+    }
+
+    // Read the content of protected regions, clipped to the flash of their target
+    async fn save_regions(&mut self, regions: Vec<ProtectedRegion>) -> anyhow::Result<Vec<SavedRegion>> {
+        let mut saved = Vec::new();
+        for region in regions {
+            let flash_end = self.layout(region.target)?.flash_end();
+            let addresses = region.addresses.start..region.addresses.end.min(flash_end);
+            if addresses.is_empty() {
+                continue;
+            }
+
+            let length = addresses.len() as u32;
+            let data = self.read_flash_internal(region.target, addresses.start, length, ProgressPhase::Reading, &mut None::<fn(&ProgressEvent)>).await?;
+            if data.len() != addresses.len() {
+                return Err(anyhow::anyhow!("Flash read of the protected {} stopped after {} of {} bytes",
+                                           region, data.len(), length));
+            }
+            saved.push(SavedRegion { region, start_address: addresses.start, data });
+        }
+        Ok(saved)
+    }
+
+    /// Write back protected regions saved with [`save_protected_regions`](Self::save_protected_regions)
+    ///
+    /// The regions are written even if protected writes are not allowed.
+    ///
+    /// # Returns
+    /// A [`FlashReport`] for each region written back
+    ///
+    /// # Errors
+    /// Returns an error if a region cannot be written, see [`flash_image`](Self::flash_image)
+    pub async fn restore_protected_regions(&mut self, saved: &[SavedRegion]) -> anyhow::Result<Vec<FlashReport>> {
+        let allow = std::mem::replace(&mut self.allow_protected_writes, true);
+        let mut reports = Vec::new();
+        let mut result = Ok(());
+        for region in saved {
+            match self.flash_image_internal(region.region.target, region.start_address, &region.data, &mut None::<fn(&ProgressEvent)>).await {
+                Ok(report) => reports.push(report),
+                Err(e) => {
+                    result = Err(e.context(format!("Cannot restore the protected {}", region.region)));
+                    break;
+                }
+            }
+        }
+        self.allow_protected_writes = allow;
+        result.map(|()| reports)
+    }
+
+    /// Write a firmware image, such as a backup of the firmware area, keeping the protected regions
+    ///
+    /// The protected regions overlapped by the image are saved first. The image is then
+    /// written at the start of the firmware area of `target` with the saved content in
+    /// place of its own bytes, so that the protected regions end up unchanged, and verified.
+    /// The first and last pages are merged even if page merging is disabled.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    /// * `image` - The image to write
+    ///
+    /// # Returns
+    /// A [`TargetUpdate`] with the flash and verification reports, the verification compares
+    /// the flash with the image as written, protected content included
+    ///
+    /// # Errors
+    /// Returns a [`VerificationFailed`] error if the flash does not read back identical, and
+    /// the errors of [`flash_image`](Self::flash_image)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn example(loader: &mut cfloader::CFLoader) -> anyhow::Result<()> {
+    /// use cfloader::bootloader;
+    ///
+    /// let backup = std::fs::read("cf2_backup.bin")?;
+    /// loader.restore_firmware(bootloader::TARGET_STM32, &backup).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn restore_firmware(&mut self, target: u8, image: &[u8]) -> anyhow::Result<TargetUpdate> {
+        self.restore_firmware_internal(target, image, &mut None::<fn(&ProgressEvent)>).await
+    }
+
+    /// Write a firmware image keeping the protected regions, with progress callback
+    ///
+    /// Same as [`restore_firmware`](Self::restore_firmware), reporting the events of the
+    /// flash and verify operations.
+    pub async fn restore_firmware_with_progress<F>(&mut self, target: u8, image: &[u8], mut progress_callback: Option<F>) -> anyhow::Result<TargetUpdate>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        self.restore_firmware_internal(target, image, &mut progress_callback).await
+    }
+
+    /// Internal restore implementation with optional progress callback
+    async fn restore_firmware_internal<F>(&mut self, target: u8, image: &[u8], progress_callback: &mut Option<F>) -> anyhow::Result<TargetUpdate>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let layout = self.layout(target)?;
+        let start_address = layout.firmware_start();
+        check_bounds(target, start_address, image.len(), start_address..layout.flash_end())?;
+
+        let addresses = start_address..start_address + image.len() as u32;
+        let regions = self.protected_regions.iter()
+            .filter(|region| region.overlaps(target, &addresses))
+            .cloned()
+            .collect();
+        let saved = self.save_regions(regions).await?;
+
+        let mut image = Cow::Borrowed(image);
+        for region in &saved {
+            region.overlay(start_address, image.to_mut());
+        }
+
+        let allow = std::mem::replace(&mut self.allow_protected_writes, true);
+        let page_merge = std::mem::replace(&mut self.page_merge, true);
+        let result = self.update_target(target, &image, progress_callback).await;
+        self.allow_protected_writes = allow;
+        self.page_merge = page_merge;
+        result
+    }
+
+    /// Compute the CRC32 and SHA-256 of a flash range
+    ///
+    /// The hashes are computed as the flash is read, the range is never held in memory.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    /// * `start_address` - The address of the first byte to hash
+    /// * `length` - The number of bytes to hash
+    ///
+    /// # Errors
+    /// Returns an error if the flash cannot be read completely
+    pub async fn hash_flash(&mut self, target: u8, start_address: u32, length: u32) -> anyhow::Result<FlashDigest> {
+        let mut hasher = FlashHasher::new();
+        let bytes_read = self.read_flash_to_sink(target, start_address, length, ProgressPhase::Reading, &mut None::<fn(&ProgressEvent)>, &mut hasher).await?;
+        if bytes_read != length {
+            return Err(anyhow::anyhow!("Flash read stopped after {} of {} bytes", bytes_read, length));
+        }
+
+        let (crc32, sha256) = hasher.finish();
+        Ok(FlashDigest {
+            target,
+            start_address,
+            length,
+            crc32,
+            sha256,
+        })
+    }
+
+    /// Update the firmware of the Crazyflie
+    ///
+    /// Flashes the nRF51 image then the STM32 image, each at the start of the firmware
+    /// area of its target, verifies each image after flashing it and resets the Crazyflie
+    /// to its firmware. The nRF51 is updated first as it is the one relaying the commands
+    /// to the STM32. Targets without image are left untouched.
+    ///
+    /// # Arguments
+    /// * `stm32_image` - The STM32 firmware image, if any
+    /// * `nrf51_image` - The nRF51 firmware image, if any
+    ///
+    /// # Returns
+    /// An [`UpdateReport`] with the flash and verification reports of each updated target
+    ///
+    /// # Errors
+    /// Returns a [`VerificationFailed`] error if an image does not read back identical, in
+    /// which case the Crazyflie is left in bootloader mode. Returns an [`OperationAborted`]
+    /// error if a flash operation is stopped, see [`flash_image`](Self::flash_image).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn example(loader: &mut cfloader::CFLoader) -> anyhow::Result<()> {
+    /// let stm32 = std::fs::read("cf2.bin")?;
+    /// let nrf51 = std::fs::read("cf2_nrf.bin")?;
+    /// let report = loader.update_firmware(Some(&stm32), Some(&nrf51)).await?;
+    /// println!("{}", report);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn update_firmware(&mut self, stm32_image: Option<&[u8]>, nrf51_image: Option<&[u8]>) -> anyhow::Result<UpdateReport> {
+        self.update_firmware_internal(stm32_image, nrf51_image, &mut None::<fn(&ProgressEvent)>).await
+    }
+
+    /// Update the firmware of the Crazyflie with progress callback
+    ///
+    /// Same as [`update_firmware`](Self::update_firmware). The callback receives the events of
+    /// the flash and verify operations of both targets, see [`ProgressEvent::target`].
+    pub async fn update_firmware_with_progress<F>(&mut self, stm32_image: Option<&[u8]>, nrf51_image: Option<&[u8]>, mut progress_callback: Option<F>) -> anyhow::Result<UpdateReport>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        self.update_firmware_internal(stm32_image, nrf51_image, &mut progress_callback).await
+    }
+
+    /// Internal update implementation with optional progress callback
+    async fn update_firmware_internal<F>(&mut self, stm32_image: Option<&[u8]>, nrf51_image: Option<&[u8]>, progress_callback: &mut Option<F>) -> anyhow::Result<UpdateReport>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let start_time = Instant::now();
+
+        let mut nrf51 = None;
+        if let Some(image) = nrf51_image {
+            nrf51 = Some(self.update_target(bootloader::TARGET_NRF51, image, progress_callback).await?);
+        }
+        let mut stm32 = None;
+        if let Some(image) = stm32_image {
+            stm32 = Some(self.update_target(bootloader::TARGET_STM32, image, progress_callback).await?);
+        }
+
+        self.reset_to_firmware().await?;
+
+        Ok(UpdateReport {
+            nrf51,
+            stm32,
+            duration: start_time.elapsed(),
+        })
+    }
+
+    // Flash and verify the firmware of one target
+    async fn update_target<F>(&mut self, target: u8, image: &[u8], progress_callback: &mut Option<F>) -> anyhow::Result<TargetUpdate>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let start_address = self.layout(target)?.firmware_start();
+
+        let flash = self.flash_image_internal(target, start_address, image, progress_callback).await?;
+        let verify = self.verify_image_internal(target, start_address, image, progress_callback).await?;
+        if !verify.is_match() {
+            return Err(VerificationFailed { report: verify }.into());
+        }
+
+        Ok(TargetUpdate { flash, verify })
+    }
+
+    /// Read flash content from the STM32 bootloader
+    ///
+    /// Convenience method that wraps [`read_flash`](Self::read_flash) for the STM32 target.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_address` - The starting address in flash to read from
+    /// * `length` - The number of bytes to read
+    ///
+    /// # Returns
+    ///
+    /// A `Vec<u8>` containing the read flash content
+    pub async fn read_stm32_flash(&mut self, start_address: u32, length: u32) -> anyhow::Result<Vec<u8>> {
+        self.read_flash(bootloader::TARGET_STM32, start_address, length).await
+    }
+
+    /// Read flash content from the nRF51 bootloader
+    ///
+    /// Convenience method that wraps [`read_flash`](Self::read_flash) for the nRF51 target.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_address` - The starting address in flash to read from
+    /// * `length` - The number of bytes to read
+    ///
+    /// # Returns
+    ///
+    /// A `Vec<u8>` containing the read flash content
     pub async fn read_nrf51_flash(&mut self, start_address: u32, length: u32) -> anyhow::Result<Vec<u8>> {
         self.read_flash(bootloader::TARGET_NRF51, start_address, length).await
     }
 
+    /// Power off the whole Crazyflie
+    ///
+    /// Both the STM32 and the nRF51 are turned off. The Crazyflie has to be switched on
+    /// again with its power button, so the loader cannot be used anymore after this call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nRF51 bootloader does not acknowledge the command
+    pub async fn power_off(&mut self) -> anyhow::Result<()> {
+        self.nrf51.all_off(&mut self.bllink).await
+    }
+
+    /// Power off the STM32
+    ///
+    /// The nRF51 stays in bootloader mode, STM32 operations fail until
+    /// [`stm32_on`](Self::stm32_on) is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nRF51 bootloader does not acknowledge the command
+    pub async fn stm32_off(&mut self) -> anyhow::Result<()> {
+        self.nrf51.sys_off(&mut self.bllink).await
+    }
+
+    /// Power on the STM32 and wait for its bootloader
+    ///
+    /// Returns once the STM32 bootloader answers GET_INFO, the STM32 information is
+    /// updated with its answer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nRF51 bootloader does not acknowledge the command or if the
+    /// STM32 bootloader does not answer within 5 seconds
+    pub async fn stm32_on(&mut self) -> anyhow::Result<()> {
+        self.nrf51.sys_on(&mut self.bllink).await?;
+        self.stm32_info = self.stm32.wait_for_info(&mut self.bllink, STM32_BOOT_TIMEOUT).await
+            .map_err(|e| e.context("STM32 bootloader not answering after power on"))?;
+        self.update_capabilities();
+        Ok(())
+    }
+
+    /// Power cycle the STM32 and wait for its bootloader
+    ///
+    /// This restarts a wedged STM32 bootloader. Returns once the STM32 bootloader answers
+    /// GET_INFO again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nRF51 bootloader does not acknowledge the commands or if the
+    /// STM32 bootloader does not answer within 5 seconds after power on
+    pub async fn power_cycle_stm32(&mut self) -> anyhow::Result<()> {
+        self.stm32_off().await?;
+        tokio::time::sleep(STM32_POWER_CYCLE_OFF_TIME).await;
+        self.stm32_on().await
+    }
+
+    /// Flash a firmware image at the start of the firmware area of a target
+    ///
+    /// Same as [`flash_image`](Self::flash_image) with the start address taken from the
+    /// [`FlashLayout`] of the target.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    /// * `image` - The firmware image
+    ///
+    /// # Errors
+    /// Returns an [`OutOfBounds`] error if the image is larger than the firmware area
+    pub async fn flash_firmware(&mut self, target: u8, image: &[u8]) -> anyhow::Result<FlashReport> {
+        let layout = self.layout(target)?;
+        self.flash_image(target, layout.firmware_start(), image).await
+    }
+
+    /// Read the firmware area of a target
+    ///
+    /// Same as [`read_flash`](Self::read_flash) with the start address taken from the
+    /// [`FlashLayout`] of the target.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    /// * `length` - The number of bytes to read, the whole firmware area if `None`
+    ///
+    /// # Errors
+    /// Returns an [`OutOfBounds`] error, before anything is read, if `length` is larger
+    /// than the firmware area
+    pub async fn read_firmware(&mut self, target: u8, length: Option<u32>) -> anyhow::Result<Vec<u8>> {
+        let layout = self.layout(target)?;
+        let length = length.unwrap_or(layout.firmware_size());
+        check_bounds(target, layout.firmware_start(), length as usize, layout.firmware_start()..layout.flash_end())?;
+        self.read_flash(target, layout.firmware_start(), length).await
+    }
+
+    /// Program a range of flash pages with a repeated pattern
+    ///
+    /// The pattern is repeated from the start of the first page to the end of the last
+    /// page and written through the RAM buffer like an image, see [`flash_image`](Self::flash_image).
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    /// * `pages` - The flash pages to program
+    /// * `pattern` - The bytes to repeat
+    ///
+    /// # Errors
+    /// Returns an error if the pattern is empty or if `pages` is empty or outside of the
+    /// firmware area of the target, before anything is written
+    pub async fn fill_pages(&mut self, target: u8, pages: Range<u16>, pattern: &[u8]) -> anyhow::Result<FlashReport> {
+        self.fill_pages_internal(target, pages, pattern, &mut None::<fn(&ProgressEvent)>).await
+    }
+
+    /// Program a range of flash pages with a repeated pattern with progress callback
+    ///
+    /// Same as [`fill_pages`](Self::fill_pages), reporting the progress events of
+    /// [`flash_image_with_progress`](Self::flash_image_with_progress).
+    pub async fn fill_pages_with_progress<F>(&mut self, target: u8, pages: Range<u16>, pattern: &[u8], mut progress_callback: Option<F>) -> anyhow::Result<FlashReport>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        self.fill_pages_internal(target, pages, pattern, &mut progress_callback).await
+    }
+
+    /// Erase a range of flash pages
+    ///
+    /// Same as [`fill_pages`](Self::fill_pages) with the erased value 0xFF.
+    pub async fn erase_pages(&mut self, target: u8, pages: Range<u16>) -> anyhow::Result<FlashReport> {
+        self.fill_pages(target, pages, &[0xFF]).await
+    }
+
+    /// Erase a range of flash pages with progress callback
+    ///
+    /// Same as [`fill_pages_with_progress`](Self::fill_pages_with_progress) with the erased value 0xFF.
+    pub async fn erase_pages_with_progress<F>(&mut self, target: u8, pages: Range<u16>, progress_callback: Option<F>) -> anyhow::Result<FlashReport>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        self.fill_pages_with_progress(target, pages, &[0xFF], progress_callback).await
+    }
+
+    /// Erase the firmware area of a target
+    ///
+    /// Without firmware the Crazyflie stays in bootloader mode when powered on. Unless
+    /// protected writes are allowed, the pages of the protected regions are kept and the
+    /// pages around them are erased separately.
+    ///
+    /// # Returns
+    /// A [`FlashReport`] for each erased range of pages
+    pub async fn erase_firmware(&mut self, target: u8) -> anyhow::Result<Vec<FlashReport>> {
+        self.erase_firmware_internal(target, &mut None::<fn(&ProgressEvent)>).await
+    }
+
+    /// Erase the firmware area of a target with progress callback
+    ///
+    /// Same as [`erase_firmware`](Self::erase_firmware). Each erased range of pages is a
+    /// separate operation with its own progress events, see
+    /// [`flash_image_with_progress`](Self::flash_image_with_progress).
+    pub async fn erase_firmware_with_progress<F>(&mut self, target: u8, mut progress_callback: Option<F>) -> anyhow::Result<Vec<FlashReport>>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        self.erase_firmware_internal(target, &mut progress_callback).await
+    }
+
+    /// Internal firmware erase implementation with optional progress callback
+    async fn erase_firmware_internal<F>(&mut self, target: u8, progress_callback: &mut Option<F>) -> anyhow::Result<Vec<FlashReport>>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let layout = self.layout(target)?;
+        let mut ranges = vec![layout.firmware_pages()];
+
+        if !self.allow_protected_writes {
+            for region in self.protected_regions.iter().filter(|region| region.target == target) {
+                let kept = layout.pages(region.addresses.clone());
+                if kept.is_empty() {
+                    continue;
+                }
+                ranges = ranges.into_iter()
+                    .flat_map(|pages| [pages.start..pages.end.min(kept.start), pages.start.max(kept.end)..pages.end])
+                    .filter(|pages| !pages.is_empty())
+                    .collect();
+            }
+        }
+
+        let mut reports = Vec::new();
+        for pages in ranges {
+            reports.push(self.fill_pages_internal(target, pages, &[0xFF], progress_callback).await?);
+        }
+        Ok(reports)
+    }
+
+    /// Internal fill implementation with optional progress callback
+    async fn fill_pages_internal<F>(&mut self, target: u8, pages: Range<u16>, pattern: &[u8], progress_callback: &mut Option<F>) -> anyhow::Result<FlashReport>
+    where
+        F: FnMut(&ProgressEvent),
+    {
+        let layout = self.layout(target)?;
+        let firmware_pages = layout.firmware_pages();
+        if pattern.is_empty() {
+            return Err(anyhow::anyhow!("Fill pattern is empty"));
+        }
+        if pages.is_empty() || pages.start < firmware_pages.start || pages.end > firmware_pages.end {
+            return Err(anyhow::anyhow!(
+                "Cannot fill pages {}..{} of target 0x{:02X}, the firmware area is pages {}..{}",
+                pages.start, pages.end, target, firmware_pages.start, firmware_pages.end
+            ));
+        }
+
+        let length = pages.len() * layout.page_size() as usize;
+        let image: Vec<u8> = pattern.iter().copied().cycle().take(length).collect();
+        let start_address = layout.address(FlashPosition { page: pages.start, offset: 0 });
+        self.flash_image_internal(target, start_address, &image, progress_callback).await
+    }
+
+    /// Identify the firmware installed on a target
+    ///
+    /// Reads the firmware area page by page until 4 consecutive erased pages, or the end
+    /// of the flash, and describes what has been found: size, SHA-256 and version strings.
+    /// Only the used part of the flash is read, so identifying a small firmware is fast.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target (use bootloader::TARGET_NRF51 or bootloader::TARGET_STM32)
+    ///
+    /// # Returns
+    /// An [`InstalledFirmware`] that can be compared to a release with [`InstalledFirmware::matches`]
+    pub async fn identify_firmware(&mut self, target: u8) -> anyhow::Result<InstalledFirmware> {
+        let layout = self.layout(target)?;
+        let page_size = layout.page_size();
+
+        let mut flash = Vec::new();
+        let mut erased_pages = 0;
+        let mut address = layout.firmware_start();
+        while address < layout.flash_end() && erased_pages < identify::ERASED_PAGES_END {
+            let page = self.read_flash(target, address, page_size).await?;
+            if identify::is_erased(&page) {
+                erased_pages += 1;
+            } else {
+                erased_pages = 0;
+            }
+            flash.extend_from_slice(&page);
+            address += page_size;
+        }
+
+        Ok(InstalledFirmware::from_flash(target, layout.firmware_start(), &flash))
+    }
+
+    /// Identify the firmware installed on both targets
+    ///
+    /// See [`identify_firmware`](Self::identify_firmware).
+    pub async fn identify_installed(&mut self) -> anyhow::Result<FirmwareInventory> {
+        Ok(FirmwareInventory {
+            nrf51: self.identify_firmware(bootloader::TARGET_NRF51).await?,
+            stm32: self.identify_firmware(bootloader::TARGET_STM32).await?,
+        })
+    }
+
+    /// Reset the Crazyflie
+    ///
+    /// Sends the reset initialization and reset commands to the nRF51 bootloader and
+    /// returns once the new state is confirmed:
+    /// - With [`ResetMode::Firmware`], once the bootloader stops answering. The loader
+    ///   cannot be used anymore as the Crazyflie runs its firmware.
+    /// - With [`ResetMode::Bootloader`], once both bootloaders answer again. The link is
+    ///   moved to the address the bootloader uses after the reset and the bootloader
+    ///   information is updated.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - What the Crazyflie boots into
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bootloader does not answer the reset initialization or if
+    /// the new state is not reached within 5 seconds
+    pub async fn reset(&mut self, mode: ResetMode) -> anyhow::Result<()> {
+        let address = self.nrf51.reset_init(&mut self.bllink).await?;
+        self.nrf51.reset(&mut self.bllink, mode).await?;
+
+        match mode {
+            ResetMode::Firmware => {
+                let deadline = Instant::now() + RESET_TIMEOUT;
+                while self.nrf51.get_info(&mut self.bllink).await.is_ok() {
+                    if Instant::now() >= deadline {
+                        return Err(anyhow::anyhow!("Bootloader still answering {:?} after reset to firmware", RESET_TIMEOUT));
+                    }
+                }
+            }
+            ResetMode::Bootloader => {
+                self.bllink.set_address(address);
+                self.nrf51_info = self.nrf51.wait_for_info(&mut self.bllink, RESET_TIMEOUT).await
+                    .map_err(|e| e.context("nRF51 bootloader not answering after reset to bootloader"))?;
+                self.stm32_info = self.stm32.wait_for_info(&mut self.bllink, RESET_TIMEOUT).await
+                    .map_err(|e| e.context("STM32 bootloader not answering after reset to bootloader"))?;
+                self.update_capabilities();
+            }
+        }
+
+        Ok(())
+    }
+
     /// Reset the Crazyflie and boot into normal firmware
     ///
-    /// Sends the reset initialization and reset commands to the nRF51 bootloader,
-    /// which will cause the Crazyflie to exit bootloader mode and boot into
-    /// normal firmware operation.
+    /// Same as [`reset`](Self::reset) with [`ResetMode::Firmware`].
     ///
     /// # Note
     ///
     /// After calling this method, the Bllink connection will no longer be valid
     /// as the Crazyflie will be running normal firmware instead of the bootloader.
     pub async fn reset_to_firmware(&mut self) -> anyhow::Result<()> {
-        let reset_init_command = vec![0xFF, bootloader::TARGET_NRF51, 0xFF];
-        self.bllink.send(&reset_init_command).await?;
+        self.reset(ResetMode::Firmware).await
+    }
+
+    /// Restart the bootloaders
+    ///
+    /// Same as [`reset`](Self::reset) with [`ResetMode::Bootloader`].
+    pub async fn reset_to_bootloader(&mut self) -> anyhow::Result<()> {
+        self.reset(ResetMode::Bootloader).await
+    }
+}
 
-        let reset_command = vec![0xFF, bootloader::TARGET_NRF51, 0xF0, 0x01];
-        self.bllink.send(&reset_command).await?;
+// Check that `length` bytes from `start_address` fit in `allowed`
+fn check_bounds(target: u8, start_address: u32, length: usize, allowed: Range<u32>) -> anyhow::Result<()> {
+    let end = u32::try_from(length).ok().and_then(|length| start_address.checked_add(length));
+    match end {
+        Some(end) if start_address >= allowed.start && end <= allowed.end => Ok(()),
+        _ => Err(OutOfBounds {
+            target,
+            start_address,
+            length: u32::try_from(length).unwrap_or(u32::MAX),
+            allowed,
+        }.into()),
+    }
+}
 
-        Ok(())
+// Bytes added around an image so that it covers whole flash pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PagePadding {
+    // Bytes of the first page before the image
+    head: usize,
+    // Bytes of the last page after the image
+    tail: usize,
+    // Whether the padding keeps the flash content, or erases the end of the last page
+    merge: bool,
+}
+
+impl PagePadding {
+    // Without merging, an unaligned start is refused since the head would be erased
+    fn new(start_address: u32, image_len: usize, page_size: usize, merge: bool) -> anyhow::Result<Self> {
+        if image_len == 0 {
+            return Ok(PagePadding { head: 0, tail: 0, merge });
+        }
+
+        let head = start_address as usize % page_size;
+        if head > 0 && !merge {
+            return Err(anyhow::anyhow!(
+                "Start address 0x{:08X} is not aligned to a {} bytes page and page merging is disabled",
+                start_address, page_size
+            ));
+        }
+        let tail = (page_size - (head + image_len) % page_size) % page_size;
+
+        Ok(PagePadding { head, tail, merge })
+    }
+
+    fn is_aligned(&self) -> bool {
+        self.head == 0 && self.tail == 0
+    }
+
+    // Flash addresses whose content changes when the padded image is written
+    fn overwritten(&self, start_address: u32, image_len: usize) -> Range<u32> {
+        let end_address = start_address + image_len as u32;
+        if self.merge {
+            start_address..end_address
+        } else {
+            start_address..end_address + self.tail as u32
+        }
+    }
+
+    // Pad the image with the flash content of its first and last pages, `tail` is
+    // ignored without merging
+    fn apply<'a>(&self, head: &[u8], image: &'a [u8], tail: &[u8]) -> Cow<'a, [u8]> {
+        if self.is_aligned() {
+            return Cow::Borrowed(image);
+        }
+
+        let mut pages = Vec::with_capacity(self.head + image.len() + self.tail);
+        pages.extend_from_slice(&head[..self.head]);
+        pages.extend_from_slice(image);
+        if self.merge {
+            pages.extend_from_slice(&tail[..self.tail]);
+        } else {
+            pages.resize(pages.len() + self.tail, 0xFF);
+        }
+        Cow::Owned(pages)
+    }
+
+    // Number of image bytes among the first `padded_bytes` bytes of the padded image
+    fn image_bytes(&self, padded_bytes: usize, image_len: usize) -> usize {
+        padded_bytes.saturating_sub(self.head).min(image_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::{Trace, TraceEntry};
+    use bootloader::TARGET_STM32;
+
+    const PAGE_SIZE: usize = 1024;
+
+    // Flash of the replayed targets: 32 bytes pages, 2 buffer pages, 16 flash pages,
+    // firmware from page 4
+    const REPLAY_PAGE_SIZE: usize = 32;
+    const REPLAY_FIRMWARE_START: usize = 4 * REPLAY_PAGE_SIZE;
+    const REPLAY_FLASH_END: usize = 16 * REPLAY_PAGE_SIZE;
+
+    // GET_INFO answer of a replayed target, without the [0xFF, target] header
+    fn replay_info(version: u8) -> InfoPacket {
+        let mut bytes = vec![0x10];
+        for value in [REPLAY_PAGE_SIZE as u16, 2, 16, 4] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.push(version);
+        InfoPacket::from_bytes(&bytes)
+    }
+
+    // Loader playing back `entries`, both bootloaders speaking `version`
+    fn replay_loader(version: u8, entries: Vec<TraceEntry>) -> CFLoader {
+        let bllink = Bllink::replay(Trace { entries });
+        CFLoader::from_parts(bllink, Bootloader::nrf51(), Bootloader::stm32(), replay_info(version), replay_info(version))
+    }
+
+    // Packet answered in its own ACK
+    fn exchange(sent: Vec<u8>, response: Vec<u8>) -> TraceEntry {
+        TraceEntry { timestamp: Duration::ZERO, sent, acked: true, response }
+    }
+
+    // STM32 command addressing a page and an offset in it
+    fn stm32_command(command: u8, address: usize) -> Vec<u8> {
+        let mut sent = vec![0xFF, TARGET_STM32, command];
+        sent.extend_from_slice(&((address / REPLAY_PAGE_SIZE) as u16).to_le_bytes());
+        sent.extend_from_slice(&((address % REPLAY_PAGE_SIZE) as u16).to_le_bytes());
+        sent
+    }
+
+    // READ_FLASH at `address`, answered with 25 bytes of `flash`
+    fn read_flash(flash: &[u8], address: usize) -> TraceEntry {
+        let sent = stm32_command(0x1C, address);
+        let mut response = sent.clone();
+        response.extend(flash[address..].iter().take(bootloader::MAX_PAYLOAD));
+        exchange(sent, response)
+    }
+
+    // LOAD_BUFFER of `data` at `offset` of the buffer
+    fn load_buffer(offset: usize, data: &[u8]) -> TraceEntry {
+        let mut sent = stm32_command(0x14, offset);
+        sent.extend_from_slice(data);
+        exchange(sent, Vec::new())
+    }
+
+    // WRITE_FLASH of `n_pages` pages of the buffer from `flash_page`, answered as done
+    fn write_flash(flash_page: u16, n_pages: u16) -> TraceEntry {
+        let mut sent = vec![0xFF, TARGET_STM32, 0x18, 0, 0];
+        sent.extend_from_slice(&flash_page.to_le_bytes());
+        sent.extend_from_slice(&n_pages.to_le_bytes());
+        exchange(sent, vec![0xFF, TARGET_STM32, 0x18, 1, 0])
+    }
+
+    // LOAD_BUFFER packets of a chunk, 25 bytes slices of each buffer page
+    fn load_chunk(chunk: &[u8]) -> Vec<TraceEntry> {
+        chunk.chunks(REPLAY_PAGE_SIZE).enumerate()
+            .flat_map(|(page, data)| data.chunks(bootloader::MAX_PAYLOAD).enumerate()
+                .map(move |(slice, data)| load_buffer(page * REPLAY_PAGE_SIZE + slice * bootloader::MAX_PAYLOAD, data)))
+            .collect()
+    }
+
+    // Flash content with a different value at each address
+    fn replay_flash() -> Vec<u8> {
+        (0..REPLAY_FLASH_END).map(|address| address as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn read_packets_are_sized_by_the_protocol_version() {
+        let flash = replay_flash();
+        let start = REPLAY_FIRMWARE_START;
+
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL,
+                                       vec![read_flash(&flash, start), read_flash(&flash, start + 25)]);
+        let data = loader.read_flash(TARGET_STM32, start as u32, 40).await.unwrap();
+        assert_eq!(data, flash[start..start + 40]);
+        assert_eq!(loader.bllink.replay_remaining(), Some(0));
+
+        let mut loader = replay_loader(Capabilities::CF1_PROTOCOL_V0,
+                                       vec![read_flash(&flash, start), read_flash(&flash, start + 16), read_flash(&flash, start + 32)]);
+        let data = loader.read_flash(TARGET_STM32, start as u32, 40).await.unwrap();
+        assert_eq!(data, flash[start..start + 40]);
+        assert_eq!(loader.bllink.replay_remaining(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn load_packets_are_sized_by_the_protocol_version() {
+        let image = [0x55; REPLAY_PAGE_SIZE];
+        let start = REPLAY_FIRMWARE_START as u32;
+
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL,
+                                       vec![load_buffer(0, &image[..25]), load_buffer(25, &image[25..]), write_flash(4, 1)]);
+        let report = loader.flash_image(TARGET_STM32, start, &image).await.unwrap();
+        assert_eq!(report.pages_written, 1);
+        assert_eq!(loader.bllink.replay_remaining(), Some(0));
+
+        // The report has the statistics of the operation: 2 LOAD_BUFFER and 1 WRITE_FLASH
+        assert_eq!(report.link_stats.packets_sent, 3);
+        assert_eq!(report.link_stats.commands[&0x14].commands, 2);
+        assert_eq!(report.link_stats.commands[&0x18].commands, 1);
+        assert_eq!(report.link_stats.total_retries(), 0);
+
+        let mut loader = replay_loader(Capabilities::CF1_PROTOCOL_V0,
+                                       vec![load_buffer(0, &image[..16]), load_buffer(16, &image[16..]), write_flash(4, 1)]);
+        let report = loader.flash_image(TARGET_STM32, start, &image).await.unwrap();
+        assert_eq!(report.pages_written, 1);
+        assert_eq!(loader.bllink.replay_remaining(), Some(0));
+    }
+
+    #[test]
+    fn aligned_image_is_not_padded() {
+        let padding = PagePadding::new(0x4000, 2 * PAGE_SIZE, PAGE_SIZE, true).unwrap();
+        assert_eq!(padding, PagePadding { head: 0, tail: 0, merge: true });
+        assert!(padding.is_aligned());
+
+        let image = [0x55; 2 * PAGE_SIZE];
+        assert!(matches!(padding.apply(&[], &image, &[]), Cow::Borrowed(_)));
+        assert_eq!(padding.overwritten(0x4000, image.len()), 0x4000..0x4800);
+    }
+
+    #[test]
+    fn unaligned_start_is_padded_with_the_first_page() {
+        let padding = PagePadding::new(0x4000 + 100, PAGE_SIZE - 100, PAGE_SIZE, true).unwrap();
+        assert_eq!(padding, PagePadding { head: 100, tail: 0, merge: true });
+
+        let head = [0x11; 100];
+        let pages = padding.apply(&head, &[0x55; PAGE_SIZE - 100], &[]);
+        assert_eq!(pages.len(), PAGE_SIZE);
+        assert_eq!(pages[..100], head);
+        assert!(pages[100..].iter().all(|&byte| byte == 0x55));
+        assert_eq!(padding.overwritten(0x4064, PAGE_SIZE - 100), 0x4064..0x4400);
+    }
+
+    #[test]
+    fn unaligned_end_is_padded_with_the_last_page() {
+        let padding = PagePadding::new(0x4000, PAGE_SIZE + 24, PAGE_SIZE, true).unwrap();
+        assert_eq!(padding, PagePadding { head: 0, tail: PAGE_SIZE - 24, merge: true });
+
+        let tail = [0x22; PAGE_SIZE - 24];
+        let pages = padding.apply(&[], &[0x55; PAGE_SIZE + 24], &tail);
+        assert_eq!(pages.len(), 2 * PAGE_SIZE);
+        assert_eq!(pages[PAGE_SIZE + 24..], tail);
+        assert_eq!(padding.overwritten(0x4000, PAGE_SIZE + 24), 0x4000..0x4418);
+    }
+
+    #[test]
+    fn image_inside_one_page_is_padded_on_both_sides() {
+        let padding = PagePadding::new(0x4000 + 10, 20, PAGE_SIZE, true).unwrap();
+        assert_eq!(padding, PagePadding { head: 10, tail: PAGE_SIZE - 30, merge: true });
+
+        let pages = padding.apply(&[0x11; 10], &[0x55; 20], &[0x22; PAGE_SIZE - 30]);
+        assert_eq!(pages.len(), PAGE_SIZE);
+        assert_eq!(pages[9..11], [0x11, 0x55]);
+        assert_eq!(pages[29..31], [0x55, 0x22]);
+
+        assert_eq!(padding.image_bytes(0, 20), 0);
+        assert_eq!(padding.image_bytes(10, 20), 0);
+        assert_eq!(padding.image_bytes(25, 20), 15);
+        assert_eq!(padding.image_bytes(PAGE_SIZE, 20), 20);
+    }
+
+    #[test]
+    fn empty_image_is_not_padded() {
+        let padding = PagePadding::new(0x4000 + 10, 0, PAGE_SIZE, false).unwrap();
+        assert!(padding.is_aligned());
+        assert_eq!(padding.apply(&[], &[], &[]).len(), 0);
+        assert_eq!(padding.overwritten(0x400A, 0), 0x400A..0x400A);
+    }
+
+    #[test]
+    fn without_merging_the_last_page_is_erased() {
+        let padding = PagePadding::new(0x4000, 24, PAGE_SIZE, false).unwrap();
+        assert_eq!(padding, PagePadding { head: 0, tail: PAGE_SIZE - 24, merge: false });
+
+        // The content of the last page is not used
+        let pages = padding.apply(&[], &[0x55; 24], &[]);
+        assert_eq!(pages.len(), PAGE_SIZE);
+        assert!(pages[24..].iter().all(|&byte| byte == 0xFF));
+        assert_eq!(padding.overwritten(0x4000, 24), 0x4000..0x4400);
+    }
+
+    #[test]
+    fn without_merging_an_unaligned_start_is_refused() {
+        let error = PagePadding::new(0x4000 + 10, 24, PAGE_SIZE, false).unwrap_err();
+        assert!(error.to_string().contains("page merging is disabled"));
+    }
+
+    fn out_of_bounds(result: anyhow::Result<()>) -> OutOfBounds {
+        result.unwrap_err().downcast::<OutOfBounds>().unwrap()
+    }
+
+    #[test]
+    fn range_ending_at_the_end_of_the_flash_is_accepted() {
+        let allowed = 0x4000..0x10_0000;
+        assert!(check_bounds(TARGET_STM32, 0x4000, 0x10_0000 - 0x4000, allowed.clone()).is_ok());
+        assert!(check_bounds(TARGET_STM32, 0x10_0000 - 1, 1, allowed.clone()).is_ok());
+        assert!(check_bounds(TARGET_STM32, 0x10_0000, 0, allowed).is_ok());
+    }
+
+    #[test]
+    fn range_one_byte_past_the_end_of_the_flash_is_rejected() {
+        let error = out_of_bounds(check_bounds(TARGET_STM32, 0x10_0000 - 1, 2, 0x4000..0x10_0000));
+        assert_eq!(error.start_address, 0x10_0000 - 1);
+        assert_eq!(error.length, 2);
+        assert_eq!(error.allowed, 0x4000..0x10_0000);
+    }
+
+    #[test]
+    fn range_starting_before_the_allowed_area_is_rejected() {
+        let error = out_of_bounds(check_bounds(TARGET_STM32, 0x3FFF, 1, 0x4000..0x10_0000));
+        assert_eq!(error.start_address, 0x3FFF);
+        assert!(check_bounds(TARGET_STM32, 0x3FFF, 1, 0..0x10_0000).is_ok());
+    }
+
+    #[test]
+    fn range_overflowing_the_address_space_is_rejected() {
+        let error = out_of_bounds(check_bounds(TARGET_STM32, u32::MAX - 1, 4, 0..u32::MAX));
+        assert_eq!(error.length, 4);
+
+        let error = out_of_bounds(check_bounds(TARGET_STM32, 0, u32::MAX as usize + 1, 0..u32::MAX));
+        assert_eq!(error.length, u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn flash_below_the_firmware_area_fails_before_any_packet() {
+        // An empty trace fails any packet sent
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, Vec::new());
+        let start = (REPLAY_FIRMWARE_START - REPLAY_PAGE_SIZE) as u32;
+        let error = loader.flash_image(TARGET_STM32, start, &[0x55; REPLAY_PAGE_SIZE]).await.unwrap_err();
+        let error = error.downcast::<OutOfBounds>().unwrap();
+        assert_eq!(error.allowed, REPLAY_FIRMWARE_START as u32..REPLAY_FLASH_END as u32);
+    }
+
+    #[tokio::test]
+    async fn read_past_the_end_of_the_flash_fails_before_any_packet() {
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, Vec::new());
+        let error = loader.read_flash(TARGET_STM32, 0, REPLAY_FLASH_END as u32 + 1).await.unwrap_err();
+        let error = error.downcast::<OutOfBounds>().unwrap();
+        assert_eq!(error.allowed, 0..REPLAY_FLASH_END as u32);
+    }
+
+    #[tokio::test]
+    async fn cancelled_token_stops_flash_before_any_packet() {
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, Vec::new());
+        let token = CancellationToken::new();
+        loader.set_cancellation_token(Some(token.clone()));
+        token.cancel();
+
+        let error = loader.flash_image(TARGET_STM32, REPLAY_FIRMWARE_START as u32, &[0x55; REPLAY_PAGE_SIZE]).await.unwrap_err();
+        let aborted = error.downcast::<OperationAborted>().unwrap();
+        assert_eq!(aborted.reason, AbortReason::Cancelled);
+        assert!(aborted.written_pages.is_empty());
+        assert_eq!(aborted.bytes_done, 0);
+    }
+
+    #[tokio::test]
+    async fn cancelling_during_flash_stops_after_the_written_pages() {
+        // 3 pages with 2 buffer pages: only the first WRITE_FLASH happens
+        let image = replay_flash()[..3 * REPLAY_PAGE_SIZE].to_vec();
+        let mut entries = load_chunk(&image[..2 * REPLAY_PAGE_SIZE]);
+        entries.push(write_flash(4, 2));
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, entries);
+
+        let token = CancellationToken::new();
+        loader.set_cancellation_token(Some(token.clone()));
+        let cancel = |event: &ProgressEvent| {
+            if event.phase == ProgressPhase::WritingFlash {
+                token.cancel();
+            }
+        };
+
+        let error = loader.flash_image_with_progress(TARGET_STM32, REPLAY_FIRMWARE_START as u32, &image, Some(cancel)).await.unwrap_err();
+        let aborted = error.downcast::<OperationAborted>().unwrap();
+        assert_eq!(aborted.reason, AbortReason::Cancelled);
+        assert_eq!(aborted.written_pages, 4..6);
+        assert_eq!(aborted.bytes_done, 2 * REPLAY_PAGE_SIZE);
+        assert_eq!(loader.bllink.replay_remaining(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn cancelling_during_read_reports_the_bytes_read() {
+        let flash = replay_flash();
+        let start = REPLAY_FIRMWARE_START;
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, vec![read_flash(&flash, start)]);
+
+        let token = CancellationToken::new();
+        loader.set_cancellation_token(Some(token.clone()));
+        let cancel = |_: &ProgressEvent| token.cancel();
+
+        let error = loader.read_flash_with_progress(TARGET_STM32, start as u32, 100, Some(cancel)).await.unwrap_err();
+        let aborted = error.downcast::<OperationAborted>().unwrap();
+        assert_eq!(aborted.reason, AbortReason::Cancelled);
+        assert!(aborted.written_pages.is_empty());
+        assert_eq!(aborted.bytes_done, 25);
+    }
+
+    #[tokio::test]
+    async fn flash_progress_counts_loaded_then_written_bytes() {
+        let image = replay_flash()[..3 * REPLAY_PAGE_SIZE].to_vec();
+        let mut entries = load_chunk(&image[..2 * REPLAY_PAGE_SIZE]);
+        entries.push(write_flash(4, 2));
+        entries.extend(load_chunk(&image[2 * REPLAY_PAGE_SIZE..]));
+        entries.push(write_flash(6, 1));
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, entries);
+
+        let mut events = Vec::new();
+        let record = |event: &ProgressEvent| events.push((event.phase, event.page, event.bytes_done, event.bytes_total));
+        loader.flash_image_with_progress(TARGET_STM32, REPLAY_FIRMWARE_START as u32, &image, Some(record)).await.unwrap();
+
+        use ProgressPhase::{LoadingBuffer, WritingFlash};
+        assert_eq!(events, [
+            (LoadingBuffer, 4, 25, 96), (LoadingBuffer, 4, 32, 96), (LoadingBuffer, 5, 57, 96), (LoadingBuffer, 5, 64, 96),
+            (WritingFlash, 4, 64, 96),
+            (LoadingBuffer, 6, 89, 96), (LoadingBuffer, 6, 96, 96),
+            (WritingFlash, 6, 96, 96),
+        ]);
     }
 
+    #[tokio::test]
+    async fn flash_progress_skips_the_merged_head_of_the_first_page() {
+        // 20 bytes image 12 bytes into a page, the first 12 bytes are read and written back
+        let flash = replay_flash();
+        let start = REPLAY_FIRMWARE_START;
+        let image = [0x55; 20];
+        let mut page = flash[start..start + 12].to_vec();
+        page.extend_from_slice(&image);
+        let mut entries = vec![read_flash(&flash, start)];
+        entries.extend(load_chunk(&page));
+        entries.push(write_flash(4, 1));
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, entries);
 
+        let mut events = Vec::new();
+        let record = |event: &ProgressEvent| events.push((event.phase, event.bytes_done, event.bytes_total));
+        let report = loader.flash_image_with_progress(TARGET_STM32, start as u32 + 12, &image, Some(record)).await.unwrap();
+        assert_eq!(report.bytes_written, 20);
 
-}
\ No newline at end of file
+        use ProgressPhase::{LoadingBuffer, WritingFlash};
+        assert_eq!(events, [(LoadingBuffer, 13, 20), (LoadingBuffer, 20, 20), (WritingFlash, 20, 20)]);
+        assert_eq!(loader.bllink.replay_remaining(), Some(0));
+    }
+
+    // READ_FLASH packets of whole pages
+    fn read_pages(flash: &[u8], pages: Range<usize>) -> Vec<TraceEntry> {
+        pages.flat_map(|page| [read_flash(flash, page * REPLAY_PAGE_SIZE), read_flash(flash, page * REPLAY_PAGE_SIZE + 25)])
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn erase_firmware_keeps_the_pages_of_protected_regions() {
+        // Pages 8 and 9 are protected, empty and out of flash regions keep nothing
+        let mut entries = Vec::new();
+        for page in [4, 6, 10, 12, 14] {
+            entries.extend(load_chunk(&[0xFF; 2 * REPLAY_PAGE_SIZE]));
+            entries.push(write_flash(page, 2));
+        }
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, entries);
+        loader.protected_regions_mut().extend([
+            ProtectedRegion::new(TARGET_STM32, 8 * REPLAY_PAGE_SIZE as u32 + 5..10 * REPLAY_PAGE_SIZE as u32, "pages 8 and 9"),
+            ProtectedRegion::new(TARGET_STM32, 12 * REPLAY_PAGE_SIZE as u32..12 * REPLAY_PAGE_SIZE as u32, "empty"),
+            ProtectedRegion::new(TARGET_STM32, u32::MAX - 1..u32::MAX, "past the flash"),
+        ]);
+
+        let reports = loader.erase_firmware(TARGET_STM32).await.unwrap();
+        let pages: Vec<_> = reports.iter().map(|report| (report.first_page, report.pages_written)).collect();
+        assert_eq!(pages, [(4, 4), (10, 6)]);
+        assert_eq!(loader.bllink.replay_remaining(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn identify_stops_after_4_erased_pages() {
+        // Firmware in pages 4 to 7 with an erased page 6, stale data in page 13
+        let mut flash = vec![0xFF; REPLAY_FLASH_END];
+        let firmware_end = 7 * REPLAY_PAGE_SIZE + 10;
+        flash[REPLAY_FIRMWARE_START..firmware_end].fill(0x42);
+        flash[6 * REPLAY_PAGE_SIZE..7 * REPLAY_PAGE_SIZE].fill(0xFF);
+        flash[REPLAY_FIRMWARE_START..REPLAY_FIRMWARE_START + 12].copy_from_slice(b"fw v2025.02\0");
+        flash[13 * REPLAY_PAGE_SIZE] = 0x00;
+
+        // Pages 8 to 11 are the 4 erased pages ending the read
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, read_pages(&flash, 4..12));
+        let installed = loader.identify_firmware(TARGET_STM32).await.unwrap();
+        assert_eq!(loader.bllink.replay_remaining(), Some(0));
+
+        let firmware = &flash[REPLAY_FIRMWARE_START..firmware_end];
+        assert_eq!(installed, InstalledFirmware::from_flash(TARGET_STM32, REPLAY_FIRMWARE_START as u32, firmware));
+        assert_eq!(installed.size, firmware.len());
+        assert_eq!(installed.version_strings, ["fw v2025.02"]);
+        assert!(installed.matches(firmware));
+    }
+
+    #[tokio::test]
+    async fn identify_stops_at_the_end_of_the_flash() {
+        // No erased page, and a last byte that is not trimmed
+        let mut flash = replay_flash();
+        flash[REPLAY_FLASH_END - 1] = 0x00;
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, read_pages(&flash, 4..16));
+        let installed = loader.identify_firmware(TARGET_STM32).await.unwrap();
+        assert_eq!(loader.bllink.replay_remaining(), Some(0));
+        assert_eq!(installed.size, REPLAY_FLASH_END - REPLAY_FIRMWARE_START);
+    }
+
+    #[tokio::test]
+    async fn identify_erased_firmware_area() {
+        let flash = vec![0xFF; REPLAY_FLASH_END];
+        let mut loader = replay_loader(Capabilities::CF2_PROTOCOL, read_pages(&flash, 4..8));
+        let installed = loader.identify_firmware(TARGET_STM32).await.unwrap();
+        assert!(installed.is_empty());
+        assert_eq!(installed, InstalledFirmware::from_flash(TARGET_STM32, REPLAY_FIRMWARE_START as u32, &[]));
+    }
+}
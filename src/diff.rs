@@ -0,0 +1,357 @@
+// Page-level comparison of flash content with an expected image
+//
+// A verification only counts the differing bytes. The diff groups them by
+// flash page, tells whether the flash is erased where the image has data
+// (typically an interrupted flash) or holds data where the image is erased
+// (stale content), and keeps a short hexdump of the start of each range.
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use serde::{Serialize, Serializer};
+
+use crate::hex::encode_hex;
+
+// Number of bytes in the hexdump excerpt of a range
+const EXCERPT_LENGTH: u32 = 16;
+
+/// Kind of difference between the flash and the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DiffKind {
+    /// The flash is erased (0xFF) where the image has data
+    Erased,
+    /// The flash holds data where the image is erased (0xFF)
+    Programmed,
+    /// The flash and the image hold different data
+    Different,
+    /// Several kinds of differences in the same page
+    Mixed,
+}
+
+impl DiffKind {
+    fn of(flash_byte: u8, image_byte: u8) -> Self {
+        match (flash_byte, image_byte) {
+            (0xFF, _) => DiffKind::Erased,
+            (_, 0xFF) => DiffKind::Programmed,
+            _ => DiffKind::Different,
+        }
+    }
+
+    fn merge(self, other: DiffKind) -> Self {
+        if self == other { self } else { DiffKind::Mixed }
+    }
+}
+
+impl Display for DiffKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DiffKind::Erased => write!(f, "erased in flash"),
+            DiffKind::Programmed => write!(f, "programmed in flash, erased in image"),
+            DiffKind::Different => write!(f, "different data"),
+            DiffKind::Mixed => write!(f, "mixed"),
+        }
+    }
+}
+
+/// Hexdump of the image and flash content at the start of a [`DiffRange`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HexExcerpt {
+    /// Address of the first byte of the excerpt
+    pub address: u32,
+    /// Content of the image, serialized as a hex string
+    #[serde(serialize_with = "serialize_hex")]
+    pub image: Vec<u8>,
+    /// Content of the flash, serialized as a hex string
+    #[serde(serialize_with = "serialize_hex")]
+    pub flash: Vec<u8>,
+}
+
+impl Display for HexExcerpt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let line = |data: &[u8]| data.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ");
+        let markers: Vec<&str> = self.image.iter().zip(&self.flash)
+            .map(|(image_byte, flash_byte)| if image_byte == flash_byte { "  " } else { "^^" })
+            .collect();
+        writeln!(f, "0x{:08X}  image {}", self.address, line(&self.image))?;
+        writeln!(f, "            flash {}", line(&self.flash))?;
+        write!(f, "                  {}", markers.join(" ").trim_end())
+    }
+}
+
+/// Range of consecutive flash pages with the same kind of difference
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffRange {
+    /// Flash pages of the range
+    pub pages: Range<u16>,
+    /// Addresses from the first to just past the last differing byte of the range
+    pub addresses: Range<u32>,
+    /// Number of differing bytes in the range
+    pub differing_bytes: usize,
+    /// Kind of difference of the pages of the range
+    pub kind: DiffKind,
+    /// Hexdump around the first differing byte of the range
+    pub excerpt: HexExcerpt,
+}
+
+impl Display for DiffRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.pages.len() == 1 {
+            write!(f, "page {}", self.pages.start)?;
+        } else {
+            write!(f, "pages {}-{}", self.pages.start, self.pages.end - 1)?;
+        }
+        write!(f, ": {} bytes differ in 0x{:08X}-0x{:08X} ({})\n{}",
+               self.differing_bytes, self.addresses.start, self.addresses.end, self.kind, self.excerpt)
+    }
+}
+
+/// Page-level differences between flash content and an image
+///
+/// Returned by [`CFLoader::diff_image`](crate::CFLoader::diff_image), or built from data
+/// read earlier with [`FlashDiff::new`].
+///
+/// # Example
+///
+/// ```
+/// use cfloader::{DiffKind, FlashDiff};
+///
+/// let image = [0x55; 2048];
+/// let mut flash = image;
+/// flash[1024..1536].fill(0xFF);
+///
+/// let diff = FlashDiff::new(0xFF, 1024, 0x4000, &flash, &image)?;
+/// assert_eq!(diff.differing_bytes, 512);
+/// assert_eq!(diff.ranges[0].pages, 17..18);
+/// assert_eq!(diff.ranges[0].kind, DiffKind::Erased);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlashDiff {
+    /// The bootloader target that has been compared
+    pub target: u8,
+    /// Address where the image is expected
+    pub start_address: u32,
+    /// Number of bytes compared
+    pub length: usize,
+    /// Size of a flash page of the target, in bytes
+    pub page_size: u32,
+    /// Total number of differing bytes
+    pub differing_bytes: usize,
+    /// Ranges of pages with differences, in address order
+    pub ranges: Vec<DiffRange>,
+}
+
+impl FlashDiff {
+    /// Compare flash content with an image
+    ///
+    /// Only the bytes present in both `flash` and `image` are compared.
+    ///
+    /// # Arguments
+    /// * `target` - The bootloader target the flash content comes from
+    /// * `page_size` - Size of a flash page of the target, in bytes
+    /// * `start_address` - Address of the first byte of `flash` and `image`
+    /// * `flash` - Content read from flash
+    /// * `image` - Expected content
+    ///
+    /// # Errors
+    /// Returns an error if `page_size` is 0, or if the compared bytes do not fit in the
+    /// 32-bit address space or in 65536 pages
+    pub fn new(target: u8, page_size: u32, start_address: u32, flash: &[u8], image: &[u8]) -> anyhow::Result<Self> {
+        if page_size == 0 {
+            return Err(anyhow::anyhow!("Invalid page size of 0 bytes"));
+        }
+        let length = flash.len().min(image.len());
+        let end_address = u32::try_from(length).ok()
+            .and_then(|length| start_address.checked_add(length))
+            .filter(|end_address| end_address.div_ceil(page_size) <= u16::MAX as u32 + 1)
+            .ok_or_else(|| anyhow::anyhow!(
+                "Cannot compare {} bytes at 0x{:08X}, they do not fit in the addresses of {} bytes pages",
+                length, start_address, page_size
+            ))?;
+        let mut ranges: Vec<DiffRange> = Vec::new();
+        let mut differing_bytes = 0;
+
+        // Differences of the page being compared
+        let mut page: Option<(u16, Range<u32>, usize, DiffKind)> = None;
+        let mut close_page = |page: (u16, Range<u32>, usize, DiffKind)| {
+            let (page, addresses, count, kind) = page;
+            match ranges.last_mut() {
+                Some(last) if last.pages.end == page && last.kind == kind => {
+                    last.pages.end = page + 1;
+                    last.addresses.end = addresses.end;
+                    last.differing_bytes += count;
+                }
+                _ => {
+                    let excerpt = excerpt(start_address, end_address, addresses.start, flash, image);
+                    ranges.push(DiffRange { pages: page..page + 1, addresses, differing_bytes: count, kind, excerpt });
+                }
+            }
+        };
+
+        for (offset, (&flash_byte, &image_byte)) in flash.iter().zip(image).enumerate() {
+            if flash_byte == image_byte {
+                continue;
+            }
+            differing_bytes += 1;
+            let address = start_address + offset as u32;
+            let page_number = (address / page_size) as u16;
+            let kind = DiffKind::of(flash_byte, image_byte);
+            match &mut page {
+                Some((number, addresses, count, page_kind)) if *number == page_number => {
+                    addresses.end = address + 1;
+                    *count += 1;
+                    *page_kind = page_kind.merge(kind);
+                }
+                _ => {
+                    if let Some(done) = page.replace((page_number, address..address + 1, 1, kind)) {
+                        close_page(done);
+                    }
+                }
+            }
+        }
+        if let Some(done) = page {
+            close_page(done);
+        }
+
+        Ok(FlashDiff {
+            target,
+            start_address,
+            length,
+            page_size,
+            differing_bytes,
+            ranges,
+        })
+    }
+
+    /// Check if the flash content matches the image
+    pub fn is_match(&self) -> bool {
+        self.differing_bytes == 0
+    }
+}
+
+impl Display for FlashDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_match() {
+            return write!(f, "{} bytes identical on target 0x{:02X} at 0x{:08X}",
+                          self.length, self.target, self.start_address);
+        }
+        write!(f, "{} of {} bytes differ on target 0x{:02X} at 0x{:08X} in {} page ranges",
+               self.differing_bytes, self.length, self.target, self.start_address, self.ranges.len())?;
+        for range in &self.ranges {
+            write!(f, "\n{}", range)?;
+        }
+        Ok(())
+    }
+}
+
+// Hexdump of the aligned line containing `address`, clipped to the compared bytes
+fn excerpt(start_address: u32, end_address: u32, address: u32, flash: &[u8], image: &[u8]) -> HexExcerpt {
+    let first = (address - address % EXCERPT_LENGTH).max(start_address);
+    let last = (first + EXCERPT_LENGTH).min(end_address);
+    let offsets = (first - start_address) as usize..(last - start_address) as usize;
+    HexExcerpt {
+        address: first,
+        image: image[offsets.clone()].to_vec(),
+        flash: flash[offsets].to_vec(),
+    }
+}
+
+fn serialize_hex<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&encode_hex(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: u32 = 16;
+
+    fn diff(flash: &[u8], image: &[u8]) -> FlashDiff {
+        FlashDiff::new(0xFF, PAGE_SIZE, 0, flash, image).unwrap()
+    }
+
+    #[test]
+    fn identical_content_matches() {
+        let diff = diff(&[0x55; 64], &[0x55; 64]);
+        assert!(diff.is_match());
+        assert!(diff.ranges.is_empty());
+        assert_eq!(diff.length, 64);
+    }
+
+    #[test]
+    fn classifies_page_differences() {
+        let image = [0x55; 64];
+        let mut flash = image;
+        flash[2] = 0xFF;
+        let erased = diff(&flash, &image);
+        assert_eq!(erased.ranges[0].kind, DiffKind::Erased);
+
+        let mut image = [0x55; 64];
+        image[3] = 0xFF;
+        let programmed = diff(&[0x55; 64], &image);
+        assert_eq!(programmed.ranges[0].kind, DiffKind::Programmed);
+
+        let mut flash = [0x55; 64];
+        flash[4] = 0x54;
+        let different = diff(&flash, &[0x55; 64]);
+        assert_eq!(different.ranges[0].kind, DiffKind::Different);
+
+        let mut flash = [0x55; 64];
+        flash[4] = 0x54;
+        flash[5] = 0xFF;
+        let mixed = diff(&flash, &[0x55; 64]);
+        assert_eq!(mixed.ranges.len(), 1);
+        assert_eq!(mixed.ranges[0].kind, DiffKind::Mixed);
+        assert_eq!(mixed.ranges[0].addresses, 4..6);
+        assert_eq!(mixed.differing_bytes, 2);
+    }
+
+    #[test]
+    fn merges_contiguous_pages_of_the_same_kind() {
+        let image = [0x55; 64];
+        let mut flash = image;
+        flash[8..40].fill(0xFF);
+        let diff = diff(&flash, &image);
+        assert_eq!(diff.ranges.len(), 1);
+        assert_eq!(diff.ranges[0].pages, 0..3);
+        assert_eq!(diff.ranges[0].addresses, 8..40);
+        assert_eq!(diff.ranges[0].differing_bytes, 32);
+    }
+
+    #[test]
+    fn splits_non_contiguous_pages_and_kinds() {
+        let image = [0x55; 64];
+        let mut flash = image;
+        flash[0] = 0xFF;
+        flash[50] = 0xFF;
+        flash[20] = 0x00;
+        let diff = diff(&flash, &image);
+        let ranges: Vec<_> = diff.ranges.iter().map(|range| (range.pages.clone(), range.kind)).collect();
+        assert_eq!(ranges, [
+            (0..1, DiffKind::Erased),
+            (1..2, DiffKind::Different),
+            (3..4, DiffKind::Erased),
+        ]);
+        assert_eq!(diff.differing_bytes, 3);
+    }
+
+    #[test]
+    fn excerpt_is_clipped_to_the_compared_bytes() {
+        let image = [0x55; 8];
+        let mut flash = image;
+        flash[6] = 0xFF;
+        let diff = FlashDiff::new(0xFF, PAGE_SIZE, 0x1004, &flash, &image).unwrap();
+        let excerpt = &diff.ranges[0].excerpt;
+        assert_eq!(excerpt.address, 0x1004);
+        assert_eq!(excerpt.flash.len(), 8);
+        assert_eq!(excerpt.flash[6], 0xFF);
+    }
+
+    #[test]
+    fn rejects_invalid_inputs() {
+        assert!(FlashDiff::new(0xFF, 0, 0, &[0; 4], &[0; 4]).is_err());
+        assert!(FlashDiff::new(0xFF, PAGE_SIZE, u32::MAX - 2, &[0; 4], &[0; 4]).is_err());
+        assert!(FlashDiff::new(0xFF, 1, 0xFFFF, &[0; 2], &[0; 2]).is_err());
+        assert!(FlashDiff::new(0xFF, 1, 0xFFFF, &[0; 1], &[0; 1]).is_ok());
+    }
+}
@@ -0,0 +1,137 @@
+// Hashes of flash content
+//
+// Digests are computed while the flash is read, so hashing a large range does
+// not need to hold its content in memory.
+
+use std::fmt::Display;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::hex::encode_hex;
+use crate::sink::ReadSink;
+
+/// CRC32 and SHA-256 of a flash range
+///
+/// Returned by [`CFLoader::hash_flash`](crate::CFLoader::hash_flash).
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example(loader: &mut cfloader::CFLoader) -> anyhow::Result<()> {
+/// use cfloader::bootloader;
+///
+/// let image = std::fs::read("cf2.bin")?;
+/// let start = loader.stm32_layout().firmware_start();
+/// let digest = loader.hash_flash(bootloader::TARGET_STM32, start, image.len() as u32).await?;
+/// println!("{}", digest);
+/// assert!(digest.matches(&image));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlashDigest {
+    /// The bootloader target
+    pub target: u8,
+    /// Address of the first byte hashed
+    pub start_address: u32,
+    /// Number of bytes hashed
+    pub length: u32,
+    /// CRC32 (IEEE) of the range
+    pub crc32: u32,
+    /// SHA-256 of the range, as lowercase hex
+    pub sha256: String,
+}
+
+impl FlashDigest {
+    /// Check if the range has the same content as `data`
+    pub fn matches(&self, data: &[u8]) -> bool {
+        let mut hasher = FlashHasher::new();
+        hasher.update(data);
+        let (crc32, sha256) = hasher.finish();
+        data.len() == self.length as usize && crc32 == self.crc32 && sha256 == self.sha256
+    }
+}
+
+impl Display for FlashDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "target 0x{:02X} 0x{:08X}-0x{:08X}: crc32 {:08x}, sha256 {}",
+               self.target, self.start_address, self.start_address + self.length, self.crc32, self.sha256)
+    }
+}
+
+// Computes both hashes of the data going through it
+pub(crate) struct FlashHasher {
+    crc32: crc32fast::Hasher,
+    sha256: Sha256,
+}
+
+impl FlashHasher {
+    pub(crate) fn new() -> Self {
+        FlashHasher {
+            crc32: crc32fast::Hasher::new(),
+            sha256: Sha256::new(),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.crc32.update(data);
+        self.sha256.update(data);
+    }
+
+    // Returns the CRC32 and hex SHA-256
+    pub(crate) fn finish(self) -> (u32, String) {
+        (self.crc32.finalize(), encode_hex(&self.sha256.finalize()))
+    }
+}
+
+impl ReadSink for FlashHasher {
+    async fn write_chunk(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.update(data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn hashes_known_vectors() {
+        let mut hasher = FlashHasher::new();
+        hasher.update(b"123456789");
+        assert_eq!(hasher.finish().0, 0xCBF43926);
+
+        let mut hasher = FlashHasher::new();
+        hasher.update(b"abc");
+        assert_eq!(hasher.finish().1, SHA256_ABC);
+    }
+
+    #[tokio::test]
+    async fn chunks_hash_like_the_whole_data() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut chunked = FlashHasher::new();
+        for chunk in data.chunks(25) {
+            chunked.write_chunk(chunk).await.unwrap();
+        }
+        let mut whole = FlashHasher::new();
+        whole.update(&data);
+        assert_eq!(chunked.finish(), whole.finish());
+    }
+
+    #[test]
+    fn digest_matches_same_content_and_length_only() {
+        let mut hasher = FlashHasher::new();
+        hasher.update(b"abc");
+        let (crc32, sha256) = hasher.finish();
+        let digest = FlashDigest { target: 0xFF, start_address: 0x4000, length: 3, crc32, sha256 };
+
+        assert!(digest.matches(b"abc"));
+        assert!(!digest.matches(b"abd"));
+        assert!(!digest.matches(b"ab"));
+        assert!(!FlashDigest { length: 4, ..digest.clone() }.matches(b"abc"));
+        assert_eq!(digest.to_string(), format!("target 0xFF 0x00004000-0x00004003: crc32 {:08x}, sha256 {}", crc32, SHA256_ABC));
+    }
+}
@@ -0,0 +1,188 @@
+//! # Typed errors returned by the high-level operations
+//!
+//! All operations return [`anyhow::Result`]. When an operation fails for one of the
+//! reasons described in this module, the typed error can be recovered with
+//! [`anyhow::Error::downcast_ref`].
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::capabilities::OptionalCommand;
+use crate::packets::FlashError;
+use crate::protect::ProtectedRegion;
+use crate::report::VerifyReport;
+
+/// Reason why an operation has been aborted before completion
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum AbortReason {
+    /// The cancellation token of the loader has been cancelled
+    Cancelled,
+    /// The operation timeout of the loader has been exceeded
+    DeadlineExceeded,
+    /// The battery voltage is below the minimum of the battery guard of the loader
+    LowBattery {
+        /// Measured battery voltage, in volts
+        voltage: f32,
+        /// Minimum voltage of the guard, in volts
+        min_voltage: f32,
+    },
+}
+
+impl Display for AbortReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AbortReason::Cancelled => write!(f, "cancelled"),
+            AbortReason::DeadlineExceeded => write!(f, "deadline exceeded"),
+            AbortReason::LowBattery { voltage, min_voltage } => {
+                write!(f, "stopped on low battery ({:.2}V, minimum {:.2}V)", voltage, min_voltage)
+            }
+        }
+    }
+}
+
+/// An operation has been stopped at a safe point before completion
+///
+/// Flash operations are never stopped while a WRITE_FLASH command is in progress:
+/// the pages in `written_pages` have been completely written and all pages after
+/// them are untouched.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OperationAborted {
+    /// Why the operation has been stopped
+    pub reason: AbortReason,
+    /// The bootloader target of the operation
+    pub target: u8,
+    /// Flash pages completely written before stopping (empty for read operations)
+    pub written_pages: Range<u16>,
+    /// Number of bytes written or read before stopping
+    pub bytes_done: usize,
+}
+
+impl Display for OperationAborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.written_pages.is_empty() {
+            write!(f, "Operation on target 0x{:02X} {} after {} bytes, no page written",
+                   self.target, self.reason, self.bytes_done)
+        } else {
+            write!(f, "Operation on target 0x{:02X} {} after {} bytes, pages {} to {} written",
+                   self.target, self.reason, self.bytes_done,
+                   self.written_pages.start, self.written_pages.end - 1)
+        }
+    }
+}
+
+impl std::error::Error for OperationAborted {}
+
+/// Flash content does not match the image that has just been written
+///
+/// Returned by operations that verify what they flash, such as
+/// [`CFLoader::update_firmware`](crate::CFLoader::update_firmware).
+#[derive(Debug, Clone, Serialize)]
+pub struct VerificationFailed {
+    /// Result of the comparison
+    pub report: VerifyReport,
+}
+
+impl Display for VerificationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Verification failed: {}", self.report)
+    }
+}
+
+impl std::error::Error for VerificationFailed {}
+
+/// A flash operation would overwrite a protected region
+///
+/// Returned before anything is written, see
+/// [`CFLoader::set_allow_protected_writes`](crate::CFLoader::set_allow_protected_writes).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProtectedRegionViolation {
+    /// The protected region
+    pub region: ProtectedRegion,
+    /// Addresses the operation would have written
+    pub addresses: Range<u32>,
+}
+
+impl Display for ProtectedRegionViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Writing 0x{:08X}-0x{:08X} would overwrite the protected {}",
+               self.addresses.start, self.addresses.end, self.region)
+    }
+}
+
+impl std::error::Error for ProtectedRegionViolation {}
+
+/// An operation addresses flash outside of the range allowed for it
+///
+/// Flash operations are limited to the firmware area of the target and read operations
+/// to its flash, as described by its [`InfoPacket`](crate::packets::InfoPacket). The range
+/// is checked before any command is sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutOfBounds {
+    /// The bootloader target of the operation
+    pub target: u8,
+    /// Start address of the operation
+    pub start_address: u32,
+    /// Number of bytes of the operation
+    pub length: u32,
+    /// Addresses the operation is allowed to access
+    pub allowed: Range<u32>,
+}
+
+impl Display for OutOfBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} bytes at 0x{:08X} are outside of 0x{:08X}-0x{:08X} on target 0x{:02X}",
+               self.length, self.start_address, self.allowed.start, self.allowed.end, self.target)
+    }
+}
+
+impl std::error::Error for OutOfBounds {}
+
+/// The bootloader has not written a flash page successfully
+///
+/// Pages before `page` have been written, `page` and the following pages of the same
+/// WRITE_FLASH command are in an unknown state.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FlashWriteFailed {
+    /// The bootloader target of the operation
+    pub target: u8,
+    /// First flash page of the failed WRITE_FLASH command
+    pub page: u16,
+    /// Error reported by the bootloader, `None` if the write is reported as not completed
+    pub error: Option<FlashError>,
+}
+
+impl Display for FlashWriteFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.error {
+            Some(error) => write!(f, "Flash operation failed at page {} of target 0x{:02X}: {}", self.page, self.target, error),
+            None => write!(f, "Flash operation at page {} of target 0x{:02X} not completed", self.page, self.target),
+        }
+    }
+}
+
+impl std::error::Error for FlashWriteFailed {}
+
+/// A bootloader does not implement a command
+///
+/// Returned before the command is sent, from the [`Capabilities`](crate::Capabilities)
+/// of the bootloader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct UnsupportedCommand {
+    /// The bootloader target
+    pub target: u8,
+    /// Protocol version of the bootloader
+    pub version: u8,
+    /// The unsupported command
+    pub command: OptionalCommand,
+}
+
+impl Display for UnsupportedCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} is not supported by the bootloader of target 0x{:02X} (protocol 0x{:02X})",
+               self.command, self.target, self.version)
+    }
+}
+
+impl std::error::Error for UnsupportedCommand {}
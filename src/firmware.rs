@@ -0,0 +1,186 @@
+//! # Entering bootloader mode from the Crazyflie firmware
+//!
+//! A Crazyflie running its firmware is rebooted into bootloader mode with the same
+//! RESET_INIT and RESET commands as the nRF51 bootloader, sent on the radio channel and
+//! address of the firmware. After the reboot the bootloader listens on the bootloader
+//! channel, at an address derived from the nRF51 unique ID and returned by RESET_INIT.
+//!
+//! Only the commands needed to reboot are implemented, this is not a CRTP stack.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use cfloader::CFLoader;
+//! use cfloader::firmware::{self, RadioUri};
+//! use crazyradio::{Crazyradio, SharedCrazyradio};
+//!
+//! let uri: RadioUri = "radio://0/80/2M/E7E7E7E7E7".parse()?;
+//! let radio = SharedCrazyradio::new(Crazyradio::open_nth_async(uri.radio).await?);
+//!
+//! let bllink = firmware::enter_bootloader(radio, &uri).await?;
+//! let loader = CFLoader::new(bllink).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crazyradio::SharedCrazyradio;
+
+use crate::Bllink;
+use crate::bootloader::{Bootloader, ResetMode};
+
+// Time given to the bootloaders to start after the reboot
+const BOOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The firmware can have other packets queued before the RESET_INIT answer
+const FIRMWARE_QUERY_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Radio URI of a Crazyflie running its firmware
+///
+/// The URI has the form `radio://<radio index>/<channel>/<datarate>/<address>`, for
+/// example `radio://0/80/2M/E7E7E7E7E7`. The address is optional and defaults to
+/// `E7E7E7E7E7`. Only the 2M datarate is supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RadioUri {
+    /// Index of the Crazyradio
+    pub radio: usize,
+    /// Radio channel, from 0 to 125
+    pub channel: u8,
+    /// Radio address
+    pub address: [u8; 5],
+}
+
+impl FromStr for RadioUri {
+    type Err = anyhow::Error;
+
+    fn from_str(uri: &str) -> anyhow::Result<Self> {
+        let invalid = |reason: &str| anyhow::anyhow!("Invalid radio URI '{}': {}", uri, reason);
+
+        let path = uri.strip_prefix("radio://").ok_or_else(|| invalid("expected radio://"))?;
+        let fields: Vec<&str> = path.split('/').collect();
+        if fields.len() != 3 && fields.len() != 4 {
+            return Err(invalid("expected radio://<radio>/<channel>/<datarate>[/<address>]"));
+        }
+
+        let radio = fields[0].parse().map_err(|_| invalid("invalid radio index"))?;
+        let channel = fields[1].parse().ok()
+            .filter(|channel| *channel <= 125)
+            .ok_or_else(|| invalid("channel must be between 0 and 125"))?;
+        if !fields[2].eq_ignore_ascii_case("2M") {
+            return Err(invalid("only the 2M datarate is supported"));
+        }
+
+        let mut address = [0xE7; 5];
+        if let Some(hex) = fields.get(3) {
+            if hex.len() != 10 || !hex.is_ascii() {
+                return Err(invalid("address must be 10 hex digits"));
+            }
+            for (i, byte) in address.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                    .map_err(|_| invalid("address must be 10 hex digits"))?;
+            }
+        }
+
+        Ok(RadioUri { radio, channel, address })
+    }
+}
+
+impl Display for RadioUri {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "radio://{}/{}/2M/", self.radio, self.channel)?;
+        for byte in self.address {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reboot a Crazyflie running its firmware into bootloader mode
+///
+/// Sends the bootloader entry sequence to the firmware at `uri` and waits until both
+/// bootloaders answer on their new address. The radio index of `uri` is not used, the
+/// commands are sent with `radio`.
+///
+/// # Arguments
+///
+/// * `radio` - The Crazyradio to use
+/// * `uri` - Radio URI of the Crazyflie firmware
+///
+/// # Returns
+///
+/// A [`Bllink`] connected to the bootloader, ready to be used with [`CFLoader`](crate::CFLoader)
+///
+/// # Errors
+///
+/// Returns an error if the firmware does not answer or if the bootloaders do not answer
+/// within 5 seconds after the reboot
+pub async fn enter_bootloader(radio: SharedCrazyradio, uri: &RadioUri) -> anyhow::Result<Bllink> {
+    let nrf51 = Bootloader::nrf51();
+
+    let mut firmware_link = Bllink::new_with_radio(radio.clone(), Some(&uri.address)).await?;
+    let channel = crazyradio::Channel::from_number(uri.channel)
+        .map_err(|_| anyhow::anyhow!("Invalid radio channel {}", uri.channel))?;
+    firmware_link.set_channel(channel);
+    let query = firmware_link.retry_policies().query.with_timeout(FIRMWARE_QUERY_TIMEOUT);
+    firmware_link.retry_policies_mut().query = query;
+
+    let address = nrf51.reset_init(&mut firmware_link).await
+        .map_err(|e| e.context(format!("Crazyflie firmware not answering on {}", uri)))?;
+    nrf51.reset(&mut firmware_link, ResetMode::Bootloader).await?;
+
+    let mut bllink = Bllink::new_with_radio(radio, Some(&address)).await?;
+    nrf51.wait_for_info(&mut bllink, BOOT_TIMEOUT).await
+        .map_err(|e| e.context("nRF51 bootloader not answering after reboot"))?;
+    Bootloader::stm32().wait_for_info(&mut bllink, BOOT_TIMEOUT).await
+        .map_err(|e| e.context("STM32 bootloader not answering after reboot"))?;
+
+    Ok(bllink)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_uri_with_address() {
+        let uri: RadioUri = "radio://1/80/2M/0102030405".parse().unwrap();
+        assert_eq!(uri, RadioUri { radio: 1, channel: 80, address: [0x01, 0x02, 0x03, 0x04, 0x05] });
+        assert_eq!(uri.to_string(), "radio://1/80/2M/0102030405");
+    }
+
+    #[test]
+    fn missing_address_defaults_to_e7() {
+        let uri: RadioUri = "radio://0/125/2m".parse().unwrap();
+        assert_eq!(uri, RadioUri { radio: 0, channel: 125, address: [0xE7; 5] });
+    }
+
+    #[test]
+    fn rejects_other_datarates() {
+        for uri in ["radio://0/80/250K", "radio://0/80/1M/E7E7E7E7E7"] {
+            let error = uri.parse::<RadioUri>().unwrap_err();
+            assert!(error.to_string().contains("2M"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_channels() {
+        for uri in ["radio://0/126/2M", "radio://0/-1/2M", "radio://0/x/2M", "radio://0//2M"] {
+            let error = uri.parse::<RadioUri>().unwrap_err();
+            assert!(error.to_string().contains("channel"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_uris() {
+        assert!("usb://0".parse::<RadioUri>().is_err());
+        assert!("radio://0/80".parse::<RadioUri>().is_err());
+        assert!("radio://0/80/2M/E7E7E7E7E7/1".parse::<RadioUri>().is_err());
+        assert!("radio://a/80/2M".parse::<RadioUri>().is_err());
+        assert!("radio://0/80/2M/E7E7E7E7".parse::<RadioUri>().is_err());
+        assert!("radio://0/80/2M/E7E7E7E7ZZ".parse::<RadioUri>().is_err());
+    }
+}
@@ -0,0 +1,52 @@
+// Hex encoding of binary data
+//
+// Every hex output of the crate (digests, diff excerpts, firmware hashes and
+// traces) is lowercase. Decoding accepts both cases.
+
+use std::fmt::Write as _;
+
+// Lowercase hex string of some bytes
+pub(crate) fn encode_hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+// Bytes of a hex string, in upper or lower case
+pub(crate) fn decode_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    // from_str_radix alone would accept a sign, such as "+1"
+    if !hex.bytes().all(|c| c.is_ascii_hexdigit()) || !hex.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!("Invalid hex string '{}'", hex));
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_lowercase() {
+        assert_eq!(encode_hex(&[0x00, 0x0A, 0xFF, 0x42]), "000aff42");
+        assert_eq!(encode_hex(&[]), "");
+    }
+
+    #[test]
+    fn decodes_both_cases() {
+        assert_eq!(decode_hex("000aff42").unwrap(), [0x00, 0x0A, 0xFF, 0x42]);
+        assert_eq!(decode_hex("000AFF42").unwrap(), [0x00, 0x0A, 0xFF, 0x42]);
+        assert_eq!(decode_hex("").unwrap(), []);
+    }
+
+    #[test]
+    fn rejects_invalid_hex() {
+        for hex in ["abc", "zz", "é0", "+1"] {
+            assert!(decode_hex(hex).is_err(), "{}", hex);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// Identification of the firmware installed on a Crazyflie
+//
+// The bootloaders do not know anything about the firmware they start, so the
+// firmware is identified from the content of the flash: the firmware area is
+// read page by page until a run of erased pages, the trailing erased bytes are
+// trimmed and what is left is hashed and searched for version strings.
+//
+// This is a heuristic: a firmware containing a large erased area, or stale data
+// left after a previous larger firmware, changes the detected size.
+
+use std::fmt::Display;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::hex::encode_hex;
+
+// Number of consecutive erased pages marking the end of the firmware
+pub(crate) const ERASED_PAGES_END: usize = 4;
+
+// Maximum number of version strings reported
+const MAX_VERSION_STRINGS: usize = 8;
+
+// Minimum length of a printable string to be considered
+const MIN_STRING_LENGTH: usize = 5;
+
+/// Description of the firmware installed on one target
+///
+/// Returned by [`CFLoader::identify_firmware`](crate::CFLoader::identify_firmware).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstalledFirmware {
+    /// The bootloader target
+    pub target: u8,
+    /// Address of the start of the firmware area
+    pub start_address: u32,
+    /// Detected size of the firmware, in bytes, 0 if the firmware area is erased
+    pub size: usize,
+    /// SHA-256 of the detected firmware, as lowercase hex
+    pub sha256: String,
+    /// Printable strings of the firmware that look like versions or build tags
+    pub version_strings: Vec<String>,
+}
+
+impl InstalledFirmware {
+    // Build the description from the content of the firmware area
+    pub(crate) fn from_flash(target: u8, start_address: u32, flash: &[u8]) -> Self {
+        let image = trim_erased(flash);
+        InstalledFirmware {
+            target,
+            start_address,
+            size: image.len(),
+            sha256: sha256_hex(image),
+            version_strings: version_strings(image),
+        }
+    }
+
+    /// Check if no firmware is installed
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Check if the installed firmware is a given release image
+    ///
+    /// Trailing erased bytes (0xFF) of the release are ignored, as they cannot be told
+    /// apart from erased flash.
+    pub fn matches(&self, release: &[u8]) -> bool {
+        let release = trim_erased(release);
+        release.len() == self.size && sha256_hex(release) == self.sha256
+    }
+}
+
+impl Display for InstalledFirmware {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no firmware at 0x{:08X}", self.start_address);
+        }
+        write!(f, "{} bytes at 0x{:08X}, sha256 {}", self.size, self.start_address, self.sha256)?;
+        if !self.version_strings.is_empty() {
+            write!(f, ", versions: {}", self.version_strings.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Description of the firmware installed on both targets
+///
+/// Returned by [`CFLoader::identify_installed`](crate::CFLoader::identify_installed).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FirmwareInventory {
+    /// Firmware installed on the nRF51
+    pub nrf51: InstalledFirmware,
+    /// Firmware installed on the STM32
+    pub stm32: InstalledFirmware,
+}
+
+impl FirmwareInventory {
+    /// Check if the installed firmware is a given release
+    ///
+    /// Targets without release image are not compared.
+    pub fn matches(&self, stm32_release: Option<&[u8]>, nrf51_release: Option<&[u8]>) -> bool {
+        stm32_release.is_none_or(|release| self.stm32.matches(release))
+            && nrf51_release.is_none_or(|release| self.nrf51.matches(release))
+    }
+}
+
+impl Display for FirmwareInventory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "nRF51: {}\nSTM32: {}", self.nrf51, self.stm32)
+    }
+}
+
+// Check if a flash page is erased
+pub(crate) fn is_erased(data: &[u8]) -> bool {
+    data.iter().all(|&byte| byte == 0xFF)
+}
+
+fn trim_erased(data: &[u8]) -> &[u8] {
+    let end = data.iter().rposition(|&byte| byte != 0xFF).map_or(0, |last| last + 1);
+    &data[..end]
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    encode_hex(&Sha256::digest(data))
+}
+
+// Printable strings containing a dotted number, such as "2025.02" or "v1.3.0"
+fn version_strings(image: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    for run in image.split(|byte| !(0x20..0x7F).contains(byte)) {
+        if run.len() < MIN_STRING_LENGTH || !looks_like_version(run) {
+            continue;
+        }
+        let string = String::from_utf8_lossy(run).trim().to_string();
+        if !strings.contains(&string) {
+            strings.push(string);
+            if strings.len() == MAX_VERSION_STRINGS {
+                break;
+            }
+        }
+    }
+    strings
+}
+
+fn looks_like_version(text: &[u8]) -> bool {
+    text.windows(3).any(|window| window[0].is_ascii_digit() && window[1] == b'.' && window[2].is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_erased_bytes_are_not_part_of_the_firmware() {
+        let mut flash = vec![0x42; 100];
+        flash[50] = 0xFF;
+        flash.resize(300, 0xFF);
+
+        let installed = InstalledFirmware::from_flash(0xFF, 0x4000, &flash);
+        assert_eq!(installed.size, 100);
+        assert_eq!(installed.sha256, sha256_hex(&flash[..100]));
+        // A release padded with 0xFF matches
+        assert!(installed.matches(&flash[..200]));
+        assert!(!installed.matches(&flash[..99]));
+    }
+
+    #[test]
+    fn erased_flash_has_no_firmware() {
+        let installed = InstalledFirmware::from_flash(0xFE, 0x16000, &[0xFF; 64]);
+        assert!(installed.is_empty());
+        assert_eq!(installed.to_string(), "no firmware at 0x00016000");
+        assert!(installed.matches(&[]));
+    }
+
+    #[test]
+    fn version_strings_contain_a_dotted_number() {
+        let image = b"\x00\x01Crazyflie v2025.02\x00build\x00abc1.2\x00x.y.z\x00Crazyflie v2025.02\x00";
+        assert_eq!(version_strings(image), ["Crazyflie v2025.02", "abc1.2"]);
+        assert!(is_erased(&[0xFF; 4]));
+        assert!(!is_erased(&[0xFF, 0xFE]));
+    }
+}
@@ -0,0 +1,218 @@
+// Flash layout of a bootloader target
+//
+// The bootloaders address flash by page and offset in page. Addresses used by
+// CFLoader are absolute in the bootloader address space: page * page_size +
+// offset, starting at 0 for the first flash page. The firmware area starts at
+// page flash_start, the pages before it hold the bootloader itself.
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::packets::InfoPacket;
+
+/// Position in flash as a page and an offset in the page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct FlashPosition {
+    /// Flash page
+    pub page: u16,
+    /// Offset in the page, in bytes
+    pub offset: u16,
+}
+
+impl Display for FlashPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "page {} offset {}", self.page, self.offset)
+    }
+}
+
+/// Flash layout of a bootloader target, built from its [`InfoPacket`]
+///
+/// Converts between absolute addresses, [`FlashPosition`]s and offsets in the
+/// firmware area.
+///
+/// # Example
+///
+/// ```no_run
+/// # fn example(loader: &cfloader::CFLoader) {
+/// let layout = loader.stm32_layout();
+///
+/// // The first byte of the firmware
+/// let start = layout.firmware_start();
+/// assert_eq!(layout.to_firmware_offset(start), Some(0));
+/// assert_eq!(layout.position(start).page, loader.stm32_info().flash_start());
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FlashLayout {
+    page_size: u32,
+    flash_start: u16,
+    n_flash_page: u16,
+}
+
+impl FlashLayout {
+    /// Size of a flash page, in bytes
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    /// Absolute address of the first byte of the firmware area
+    pub fn firmware_start(&self) -> u32 {
+        self.flash_start as u32 * self.page_size
+    }
+
+    /// Absolute address just past the end of the flash
+    pub fn flash_end(&self) -> u32 {
+        self.n_flash_page as u32 * self.page_size
+    }
+
+    /// Size of the firmware area, in bytes
+    pub fn firmware_size(&self) -> u32 {
+        self.flash_end().saturating_sub(self.firmware_start())
+    }
+
+    /// Flash pages of the firmware area
+    pub fn firmware_pages(&self) -> Range<u16> {
+        self.flash_start..self.n_flash_page
+    }
+
+    /// Check if an absolute address is in the firmware area
+    pub fn contains(&self, address: u32) -> bool {
+        (self.firmware_start()..self.flash_end()).contains(&address)
+    }
+
+    /// Page and offset of an absolute address
+    pub fn position(&self, address: u32) -> FlashPosition {
+        FlashPosition {
+            page: (address / self.page_size) as u16,
+            offset: (address % self.page_size) as u16,
+        }
+    }
+
+    /// Flash pages covering an address range, clipped to the end of the flash
+    ///
+    /// Returns an empty range if `addresses` is empty or past the end of the flash.
+    pub fn pages(&self, addresses: Range<u32>) -> Range<u16> {
+        let start = addresses.start.min(self.flash_end());
+        let end = addresses.end.clamp(start, self.flash_end());
+        // Both ends are at most n_flash_page once clipped to the flash
+        (start / self.page_size) as u16..end.div_ceil(self.page_size) as u16
+    }
+
+    /// Absolute address of a page and offset
+    pub fn address(&self, position: FlashPosition) -> u32 {
+        position.page as u32 * self.page_size + position.offset as u32
+    }
+
+    /// Offset in the firmware area of an absolute address
+    ///
+    /// Returns `None` if the address is outside of the firmware area.
+    pub fn to_firmware_offset(&self, address: u32) -> Option<u32> {
+        self.contains(address).then(|| address - self.firmware_start())
+    }
+
+    /// Absolute address of an offset in the firmware area
+    ///
+    /// Returns `None` if the offset is past the end of the firmware area.
+    pub fn from_firmware_offset(&self, offset: u32) -> Option<u32> {
+        (offset < self.firmware_size()).then(|| self.firmware_start() + offset)
+    }
+}
+
+impl From<&InfoPacket> for FlashLayout {
+    fn from(info: &InfoPacket) -> Self {
+        FlashLayout {
+            page_size: info.page_size() as u32,
+            flash_start: info.flash_start(),
+            n_flash_page: info.n_flash_page(),
+        }
+    }
+}
+
+impl Display for FlashLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "firmware at 0x{:08X}-0x{:08X} ({} bytes, {} bytes pages)",
+               self.firmware_start(), self.flash_end(), self.firmware_size(), self.page_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Layout reported by a bootloader: 1024 bytes pages, `n_flash_page` pages, firmware from `flash_start`
+    fn layout(flash_start: u16, n_flash_page: u16) -> FlashLayout {
+        let mut bytes = vec![0x10];
+        for value in [1024, 10, n_flash_page, flash_start] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.push(0x10);
+        FlashLayout::from(&InfoPacket::from_bytes(&bytes))
+    }
+
+    // STM32 layout: 1 MB of flash, firmware from 0x4000
+    fn stm32() -> FlashLayout {
+        layout(16, 1024)
+    }
+
+    // nRF51 layout: 232 pages of flash, firmware from page 88
+    fn nrf51() -> FlashLayout {
+        layout(88, 232)
+    }
+
+    #[test]
+    fn firmware_area_of_both_targets() {
+        assert_eq!((stm32().firmware_start(), stm32().flash_end(), stm32().firmware_size()), (0x4000, 0x10_0000, 0xFC000));
+        assert_eq!(stm32().firmware_pages(), 16..1024);
+        assert_eq!((nrf51().firmware_start(), nrf51().flash_end(), nrf51().firmware_size()), (0x16000, 0x3A000, 0x24000));
+        assert_eq!(nrf51().firmware_pages(), 88..232);
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        for layout in [stm32(), nrf51()] {
+            for address in [0, 1023, 1024, layout.firmware_start() - 1, layout.firmware_start() + 25, layout.flash_end() - 1, layout.flash_end()] {
+                assert_eq!(layout.address(layout.position(address)), address);
+            }
+        }
+        assert_eq!(stm32().position(0x4019), FlashPosition { page: 16, offset: 25 });
+        // The end of the flash is the first byte of the page past the last one
+        assert_eq!(stm32().position(0x10_0000), FlashPosition { page: 1024, offset: 0 });
+    }
+
+    #[test]
+    fn pages_cover_the_address_range_clipped_to_the_flash() {
+        assert_eq!(stm32().pages(0x4001..0x4401), 16..18);
+        assert_eq!(stm32().pages(0xE_0000..0x10_0000), 896..1024);
+        assert_eq!(nrf51().pages(0x3_9000..0x4_0000), 228..232);
+        assert!(stm32().pages(0x4000..0x4000).is_empty());
+        assert!(stm32().pages(0x10_0000..u32::MAX).is_empty());
+        assert!(nrf51().pages(0xE_0000..0x10_0000).is_empty());
+    }
+
+    #[test]
+    fn firmware_offsets_round_trip_inside_the_firmware_area() {
+        for layout in [stm32(), nrf51()] {
+            let start = layout.firmware_start();
+            for offset in [0, 1, layout.firmware_size() - 1] {
+                let address = layout.from_firmware_offset(offset).unwrap();
+                assert_eq!(address, start + offset);
+                assert_eq!(layout.to_firmware_offset(address), Some(offset));
+            }
+        }
+    }
+
+    #[test]
+    fn addresses_outside_of_the_firmware_area_have_no_offset() {
+        for layout in [stm32(), nrf51()] {
+            assert_eq!(layout.to_firmware_offset(0), None);
+            assert_eq!(layout.to_firmware_offset(layout.firmware_start() - 1), None);
+            assert_eq!(layout.to_firmware_offset(layout.flash_end()), None);
+            assert_eq!(layout.from_firmware_offset(layout.firmware_size()), None);
+            assert!(!layout.contains(layout.flash_end()));
+            assert!(layout.contains(layout.flash_end() - 1));
+        }
+    }
+}
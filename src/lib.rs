@@ -16,7 +16,7 @@
 //! it can be programmed and worked with. The radio bootloader gives access to two
 //! separate chip bootloaders:
 //! - The STM32 bootloader, which is used to program the main flight controller
-//!  chip.
+//!   chip.
 //! - The nRF51 bootloader, which is used to program the Crazyradio chip.
 //! 
 //! The nRF51 bootloader also acts as a proxy between the Crazyradio and the STM32
@@ -32,18 +32,49 @@
 //! the Crazyflie is in firmware mode or by holding the power switch for about
 //! 2 seconds when powering on the Crazyflie.
 //! 
-//! Sending commands to enter bootloader mode is left as an exercise for another
-//! crate (such as [cflib](https://github.com/bitcraze/crazyflie-lib-rs)).
+//! The [`firmware`] module sends the commands to enter bootloader mode from the
+//! firmware. Any other communication with the firmware is left to another crate
+//! (such as [cflib](https://github.com/bitcraze/crazyflie-lib-rs)).
 //! 
 //! See examples in the repository for how to use this crate.
 
 #![deny(missing_docs)]
 
+mod battery;
 mod bllink;
 pub mod bootloader;
+mod cancel;
+mod capabilities;
 mod cfloader;
+mod diff;
+mod digest;
+pub mod error;
+pub mod firmware;
+mod hex;
+mod identify;
+mod layout;
 pub mod packets;
+mod progress;
+mod protect;
+mod report;
+mod retry;
+mod sink;
+mod stats;
+mod trace;
 
+pub use battery::{BatteryGuard, BatteryMonitor, BatteryReading, BatteryState};
 pub use bllink::Bllink;
 pub use bootloader::Bootloader;
+pub use cancel::CancellationToken;
+pub use capabilities::{Capabilities, OptionalCommand};
 pub use cfloader::CFLoader;
+pub use diff::{DiffKind, DiffRange, FlashDiff, HexExcerpt};
+pub use digest::FlashDigest;
+pub use identify::{FirmwareInventory, InstalledFirmware};
+pub use layout::{FlashLayout, FlashPosition};
+pub use progress::{ProgressEvent, ProgressPhase};
+pub use protect::{ProtectedRegion, SavedRegion};
+pub use report::{FlashReport, TargetUpdate, UpdateReport, VerifyReport};
+pub use retry::{Backoff, CommandClass, RetryPolicies, RetryPolicy};
+pub use stats::{CommandStats, LinkStats, RttHistogram, RTT_BUCKETS_MS};
+pub use trace::{Trace, TraceEntry};
@@ -5,6 +5,8 @@
 
 use std::{fmt::Debug, fmt::Display};
 
+use serde::Serialize;
+
 // Info packet structure:
 // [0xff, target, 0x10, pageSize, nBuffPage, nFlashPage, flashStart, cpuId, version]
 //
@@ -29,6 +31,7 @@ use std::{fmt::Debug, fmt::Display};
 /// * `flash_start` - Start flash page of firmware area
 /// * `cpu_id` - Legacy CPU ID (12 bytes, should be ignored)
 /// * `version` - Bootloader protocol version
+#[derive(Clone, Serialize)]
 pub struct InfoPacket {
     page_size: u16,
     n_buff_page: u16,
@@ -198,6 +201,8 @@ impl FlashWriteResponse {
 
     /// Check if the flash operation has completed
     ///
+    /// A completed operation is not necessarily successful, see [`status`](Self::status).
+    ///
     /// # Returns
     ///
     /// `true` if the operation is done, `false` if still in progress
@@ -205,6 +210,18 @@ impl FlashWriteResponse {
         self.done != 0
     }
 
+    /// Get the state of the flash operation
+    ///
+    /// An error code other than 0 means that the operation has failed, whether or not it is
+    /// reported as done. Unknown error codes are failures.
+    pub fn status(&self) -> FlashWriteStatus {
+        match self.error() {
+            FlashError::NoError if self.is_done() => FlashWriteStatus::Done,
+            FlashError::NoError => FlashWriteStatus::InProgress,
+            error => FlashWriteStatus::Failed(error),
+        }
+    }
+
     /// Get the error status as an enum
     ///
     /// # Returns
@@ -220,7 +237,7 @@ impl FlashWriteResponse {
     ///
     /// `true` if the operation is done and no error occurred
     pub fn is_success(&self) -> bool {
-        self.is_done() && self.error() == FlashError::NoError
+        self.status() == FlashWriteStatus::Done
     }
 }
 
@@ -233,6 +250,27 @@ impl Debug for FlashWriteResponse {
     }
 }
 
+/// State of a flash operation, as reported by a [`FlashWriteResponse`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum FlashWriteStatus {
+    /// The operation is still in progress
+    InProgress,
+    /// The operation has completed without error
+    Done,
+    /// The operation has failed
+    Failed(FlashError),
+}
+
+impl FlashWriteStatus {
+    /// Get the error of a failed operation
+    pub fn error(&self) -> Option<FlashError> {
+        match self {
+            FlashWriteStatus::Failed(error) => Some(*error),
+            _ => None,
+        }
+    }
+}
+
 /// Response from a flash status query
 ///
 /// This is an alias for [`FlashWriteResponse`] as both use the same response format.
@@ -286,16 +324,31 @@ impl Debug for FlashReadPacket {
 ///
 /// Represents the possible error conditions that can occur during flash
 /// memory operations like erase and programming.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum FlashError {
     /// No error occurred
-    NoError = 0,
+    NoError,
     /// The specified address is outside valid boundaries
-    AddressOutOfBounds = 1,
+    AddressOutOfBounds,
     /// Flash erase operation failed
-    FlashEraseFailed = 2,
+    FlashEraseFailed,
     /// Flash programming operation failed
-    FlashProgrammingFailed = 3,
+    FlashProgrammingFailed,
+    /// Error code unknown to this library, reported by a newer bootloader
+    Unknown(u8),
+}
+
+impl FlashError {
+    /// Get the error code as sent by the bootloader
+    pub fn code(&self) -> u8 {
+        match self {
+            FlashError::NoError => 0,
+            FlashError::AddressOutOfBounds => 1,
+            FlashError::FlashEraseFailed => 2,
+            FlashError::FlashProgrammingFailed => 3,
+            FlashError::Unknown(code) => *code,
+        }
+    }
 }
 
 impl From<u8> for FlashError {
@@ -305,7 +358,7 @@ impl From<u8> for FlashError {
             1 => FlashError::AddressOutOfBounds,
             2 => FlashError::FlashEraseFailed,
             3 => FlashError::FlashProgrammingFailed,
-            _ => FlashError::NoError, // Default to no error for unknown codes
+            code => FlashError::Unknown(code),
         }
     }
 }
@@ -317,6 +370,51 @@ impl Display for FlashError {
             FlashError::AddressOutOfBounds => write!(f, "Addresses are outside of authorized boundaries"),
             FlashError::FlashEraseFailed => write!(f, "Flash erase failed"),
             FlashError::FlashProgrammingFailed => write!(f, "Flash programming failed"),
+            FlashError::Unknown(code) => write!(f, "Unknown flash error code {}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_flash_error_codes_round_trip() {
+        for code in 0..=3 {
+            let error = FlashError::from(code);
+            assert!(!matches!(error, FlashError::Unknown(_)));
+            assert_eq!(error.code(), code);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unknown_flash_error_code_is_kept() {
+        assert_eq!(FlashError::from(0x42), FlashError::Unknown(0x42));
+        assert_eq!(FlashError::Unknown(0x42).code(), 0x42);
+    }
+
+    #[test]
+    fn write_status_follows_done_and_error() {
+        let response = FlashWriteResponse::from_bytes(&[0x18, 0, 0]);
+        assert_eq!(response.status(), FlashWriteStatus::InProgress);
+        assert!(!response.is_success());
+
+        let response = FlashWriteResponse::from_bytes(&[0x18, 1, 0]);
+        assert_eq!(response.status(), FlashWriteStatus::Done);
+        assert!(response.is_success());
+    }
+
+    #[test]
+    fn error_fails_a_done_write() {
+        let response = FlashWriteResponse::from_bytes(&[0x18, 1, 2]);
+        assert!(response.is_done());
+        assert_eq!(response.status(), FlashWriteStatus::Failed(FlashError::FlashEraseFailed));
+        assert!(!response.is_success());
+
+        let response = FlashWriteResponse::from_bytes(&[0x18, 1, 0x42]);
+        assert_eq!(response.status(), FlashWriteStatus::Failed(FlashError::Unknown(0x42)));
+        assert_eq!(response.status().error(), Some(FlashError::Unknown(0x42)));
+        assert!(!response.is_success());
+    }
+}
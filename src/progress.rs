@@ -0,0 +1,191 @@
+// Progress reporting for long-running CFLoader operations
+
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// Phase of a long-running operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressPhase {
+    /// Data is being loaded in the bootloader RAM buffer
+    LoadingBuffer,
+    /// The loaded RAM buffer is being read back and compared
+    ///
+    /// The bytes done do not change while checking, they already count the loaded bytes.
+    CheckingBuffer,
+    /// The RAM buffer has been written to flash
+    ///
+    /// Reported after each successful WRITE_FLASH, the flash operation is complete once
+    /// this event reports all the bytes.
+    WritingFlash,
+    /// Flash content is being read back and compared
+    Verifying,
+    /// Flash content is being read
+    Reading,
+}
+
+impl Display for ProgressPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProgressPhase::LoadingBuffer => write!(f, "Loading buffer"),
+            ProgressPhase::CheckingBuffer => write!(f, "Checking buffer"),
+            ProgressPhase::WritingFlash => write!(f, "Writing flash"),
+            ProgressPhase::Verifying => write!(f, "Verifying"),
+            ProgressPhase::Reading => write!(f, "Reading"),
+        }
+    }
+}
+
+/// Progress event reported by long-running [`CFLoader`](crate::CFLoader) operations
+///
+/// Events are reported after each radio command of the operation, so the progress
+/// moves smoothly even when each WRITE_FLASH covers many pages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressEvent {
+    /// Current phase of the operation
+    pub phase: ProgressPhase,
+    /// Bootloader target of the operation
+    pub target: u8,
+    /// Flash page being processed
+    pub page: u16,
+    /// Number of bytes processed so far
+    ///
+    /// When loading the RAM buffer, this includes the bytes loaded that are not yet
+    /// written to flash. [`ProgressPhase::WritingFlash`] events only count the bytes
+    /// written to flash.
+    pub bytes_done: usize,
+    /// Total number of bytes of the operation
+    ///
+    /// When flashing, this is the size of the image: the flash content written back
+    /// around it to complete the first and last pages is not counted.
+    pub bytes_total: usize,
+    /// Number of command retries since the beginning of the operation
+    pub retries: u64,
+    /// Estimated time until the end of the operation, once it can be estimated
+    pub eta: Option<Duration>,
+}
+
+impl ProgressEvent {
+    /// Progress of the operation, between 0 and 1
+    pub fn fraction(&self) -> f64 {
+        if self.bytes_total == 0 {
+            1.0
+        } else {
+            self.bytes_done as f64 / self.bytes_total as f64
+        }
+    }
+}
+
+impl Display for ProgressEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}/{} bytes ({:.1}%), page {}, {} retries",
+               self.phase, self.bytes_done, self.bytes_total, self.fraction() * 100.0,
+               self.page, self.retries)?;
+        if let Some(eta) = self.eta {
+            write!(f, ", {}s remaining", eta.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+// Builds the progress events of one operation
+pub(crate) struct ProgressTracker {
+    target: u8,
+    bytes_total: usize,
+    skipped: usize,
+    retries_before: u64,
+    start: Instant,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(target: u8, bytes_total: usize, retries_before: u64) -> Self {
+        ProgressTracker {
+            target,
+            bytes_total,
+            skipped: 0,
+            retries_before,
+            start: Instant::now(),
+        }
+    }
+
+    // Do not count the first bytes processed, such as the flash content written back
+    // before an image
+    pub(crate) fn skipping(mut self, skipped: usize) -> Self {
+        self.skipped = skipped;
+        self
+    }
+
+    pub(crate) fn event(&self, phase: ProgressPhase, page: u16, bytes_done: usize, retries: u64) -> ProgressEvent {
+        let bytes_done = bytes_done.saturating_sub(self.skipped).min(self.bytes_total);
+        let eta = if bytes_done > 0 {
+            let elapsed = self.start.elapsed().as_secs_f64();
+            let remaining = self.bytes_total.saturating_sub(bytes_done) as f64;
+            Some(Duration::from_secs_f64(elapsed * remaining / bytes_done as f64))
+        } else {
+            None
+        };
+
+        ProgressEvent {
+            phase,
+            target: self.target,
+            page,
+            bytes_done,
+            bytes_total: self.bytes_total,
+            retries: retries.saturating_sub(self.retries_before),
+            eta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_count_bytes_against_the_total() {
+        let tracker = ProgressTracker::new(0xFF, 1000, 0);
+        for (bytes_done, fraction) in [(0, 0.0), (250, 0.25), (1000, 1.0)] {
+            let event = tracker.event(ProgressPhase::LoadingBuffer, 4, bytes_done, 0);
+            assert_eq!((event.bytes_done, event.bytes_total), (bytes_done, 1000));
+            assert_eq!(event.fraction(), fraction);
+        }
+
+        // Never past the total
+        assert_eq!(tracker.event(ProgressPhase::WritingFlash, 4, 1024, 0).bytes_done, 1000);
+    }
+
+    #[test]
+    fn eta_is_only_estimated_once_bytes_are_done() {
+        let tracker = ProgressTracker::new(0xFF, 1000, 0);
+        assert_eq!(tracker.event(ProgressPhase::LoadingBuffer, 4, 0, 0).eta, None);
+        assert!(tracker.event(ProgressPhase::LoadingBuffer, 4, 25, 0).eta.is_some());
+        assert_eq!(tracker.event(ProgressPhase::WritingFlash, 4, 1000, 0).eta, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retries_are_counted_from_the_start_of_the_operation() {
+        let tracker = ProgressTracker::new(0xFF, 1000, 7);
+        assert_eq!(tracker.event(ProgressPhase::Reading, 4, 25, 7).retries, 0);
+        assert_eq!(tracker.event(ProgressPhase::Reading, 4, 50, 10).retries, 3);
+    }
+
+    #[test]
+    fn skipped_bytes_are_not_counted_as_done() {
+        // 100 bytes of flash content written back before a 900 bytes image
+        let tracker = ProgressTracker::new(0xFF, 900, 0).skipping(100);
+        assert_eq!(tracker.event(ProgressPhase::LoadingBuffer, 4, 25, 0).bytes_done, 0);
+        assert_eq!(tracker.event(ProgressPhase::LoadingBuffer, 4, 25, 0).eta, None);
+        assert_eq!(tracker.event(ProgressPhase::LoadingBuffer, 4, 100, 0).bytes_done, 0);
+        assert_eq!(tracker.event(ProgressPhase::LoadingBuffer, 4, 125, 0).bytes_done, 25);
+
+        // Once the pages are written, the whole image is done
+        let event = tracker.event(ProgressPhase::WritingFlash, 4, 1024, 0);
+        assert_eq!((event.bytes_done, event.bytes_total), (900, 900));
+    }
+
+    #[test]
+    fn empty_operation_is_complete() {
+        let event = ProgressTracker::new(0xFF, 0, 0).event(ProgressPhase::Reading, 0, 0, 0);
+        assert_eq!(event.fraction(), 1.0);
+        assert_eq!(event.to_string(), "Reading: 0/0 bytes (100.0%), page 0, 0 retries");
+    }
+}
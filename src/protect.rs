@@ -0,0 +1,157 @@
+// Flash regions protected from flash operations
+//
+// Some flash content belongs to the Crazyflie rather than to the firmware image:
+// data stored by the firmware survives a firmware update only if it is not
+// overwritten. Flash operations of CFLoader refuse to write to a protected
+// region unless protected writes are explicitly allowed.
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::bootloader;
+
+/// Flash range that flash operations refuse to overwrite
+///
+/// Addresses are absolute in the bootloader address space of the target, as everywhere
+/// in [`CFLoader`](crate::CFLoader).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProtectedRegion {
+    /// The bootloader target of the region
+    pub target: u8,
+    /// Addresses of the region
+    pub addresses: Range<u32>,
+    /// Name of the region, used in error messages
+    pub name: String,
+}
+
+impl ProtectedRegion {
+    /// Last 128 KB sector of the STM32F405 flash (sector 11, 0x080E0000 to 0x08100000)
+    ///
+    /// The STM32 erases flash by sector, this is the sector a firmware storing data in
+    /// flash can keep apart from its own image.
+    pub const STM32_STORAGE_SECTOR: Range<u32> = 0x000E_0000..0x0010_0000;
+
+    /// Create a protected region
+    pub fn new(target: u8, addresses: Range<u32>, name: impl Into<String>) -> Self {
+        ProtectedRegion {
+            target,
+            addresses,
+            name: name.into(),
+        }
+    }
+
+    /// Regions protected by default on a target
+    ///
+    /// The STM32 storage sector is protected on the STM32, nothing is protected on the
+    /// nRF51.
+    pub fn defaults(target: u8) -> Vec<ProtectedRegion> {
+        match target {
+            bootloader::TARGET_STM32 => vec![ProtectedRegion::new(target, Self::STM32_STORAGE_SECTOR, "STM32 storage sector")],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Check if an address range of a target overlaps the region
+    ///
+    /// Empty ranges and empty regions overlap nothing.
+    pub fn overlaps(&self, target: u8, addresses: &Range<u32>) -> bool {
+        self.target == target && !self.addresses.is_empty() && !addresses.is_empty()
+            && addresses.start < self.addresses.end && self.addresses.start < addresses.end
+    }
+}
+
+impl Display for ProtectedRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} (target 0x{:02X}, 0x{:08X}-0x{:08X})",
+               self.name, self.target, self.addresses.start, self.addresses.end)
+    }
+}
+
+/// Content of a protected region saved from flash
+///
+/// Returned by [`CFLoader::save_protected_regions`](crate::CFLoader::save_protected_regions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedRegion {
+    /// The saved region
+    pub region: ProtectedRegion,
+    /// Address of the first saved byte
+    pub start_address: u32,
+    /// Content of the region, up to the end of the flash of the target
+    pub data: Vec<u8>,
+}
+
+impl SavedRegion {
+    /// Copy the saved content over the part of an image covering the region
+    ///
+    /// # Arguments
+    /// * `start_address` - Address of the first byte of `image`, on the target of the region
+    /// * `image` - The image to update
+    pub fn overlay(&self, start_address: u32, image: &mut [u8]) {
+        let image_end = start_address as u64 + image.len() as u64;
+        let saved_end = self.start_address as u64 + self.data.len() as u64;
+        let first = self.start_address.max(start_address) as u64;
+        let end = saved_end.min(image_end);
+        if first >= end {
+            return;
+        }
+
+        let image_range = (first - start_address as u64) as usize..(end - start_address as u64) as usize;
+        let saved_range = (first - self.start_address as u64) as usize..(end - self.start_address as u64) as usize;
+        image[image_range].copy_from_slice(&self.data[saved_range]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(start_address: u32, data: Vec<u8>) -> SavedRegion {
+        let end = start_address + data.len() as u32;
+        SavedRegion {
+            region: ProtectedRegion::new(bootloader::TARGET_STM32, start_address..end, "test"),
+            start_address,
+            data,
+        }
+    }
+
+    #[test]
+    fn overlay_replaces_the_covered_part_of_the_image() {
+        let mut image = vec![0x55; 16];
+        saved(0x1008, vec![0xAA; 16]).overlay(0x1000, &mut image);
+        assert_eq!(image[..8], [0x55; 8]);
+        assert_eq!(image[8..], [0xAA; 8]);
+
+        let mut image = vec![0x55; 16];
+        saved(0x0FF8, vec![0xAA; 12]).overlay(0x1000, &mut image);
+        assert_eq!(image[..4], [0xAA; 4]);
+        assert_eq!(image[4..], [0x55; 12]);
+    }
+
+    #[test]
+    fn overlay_ignores_regions_outside_of_the_image() {
+        let mut image = vec![0x55; 16];
+        saved(0x1010, vec![0xAA; 16]).overlay(0x1000, &mut image);
+        saved(0x0FF0, vec![0xAA; 16]).overlay(0x1000, &mut image);
+        assert_eq!(image, [0x55; 16]);
+    }
+
+    #[test]
+    fn default_regions_protect_the_stm32_storage_sector() {
+        let regions = ProtectedRegion::defaults(bootloader::TARGET_STM32);
+        assert_eq!(regions.len(), 1);
+        assert!(regions[0].overlaps(bootloader::TARGET_STM32, &(0x000D_FFFF..0x000E_0001)));
+        assert!(!regions[0].overlaps(bootloader::TARGET_STM32, &(0x0000_4000..0x000E_0000)));
+        assert!(!regions[0].overlaps(bootloader::TARGET_NRF51, &(0x000E_0000..0x000E_0001)));
+        assert!(ProtectedRegion::defaults(bootloader::TARGET_NRF51).is_empty());
+    }
+
+    #[test]
+    fn empty_ranges_overlap_nothing() {
+        let empty = ProtectedRegion::new(bootloader::TARGET_STM32, 0x8000..0x8000, "empty");
+        assert!(!empty.overlaps(bootloader::TARGET_STM32, &(0x4000..0x10000)));
+        let region = ProtectedRegion::new(bootloader::TARGET_STM32, 0x4000..0x10000, "region");
+        assert!(!region.overlaps(bootloader::TARGET_STM32, &(0x8000..0x8000)));
+    }
+}
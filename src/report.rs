@@ -0,0 +1,249 @@
+// Reports returned by the high-level CFLoader operations
+
+use std::fmt::Display;
+use std::io::Read;
+use std::ops::Range;
+use std::time::Duration;
+
+use serde::{Serialize, Serializer};
+
+use crate::sink::ReadSink;
+use crate::stats::LinkStats;
+
+/// Report of a flash operation
+///
+/// Returned by the flash methods of [`CFLoader`](crate::CFLoader).
+#[derive(Debug, Clone, Serialize)]
+pub struct FlashReport {
+    /// The bootloader target that has been flashed
+    pub target: u8,
+    /// Address where the image has been written
+    pub start_address: u32,
+    /// Number of image bytes written
+    pub bytes_written: usize,
+    /// First flash page written
+    pub first_page: u16,
+    /// Number of flash pages written
+    pub pages_written: u16,
+    /// Number of buffer slices loaded again after a corrupted read-back, see
+    /// [`CFLoader::set_buffer_readback`](crate::CFLoader::set_buffer_readback)
+    pub buffer_reloads: usize,
+    /// Time taken by the operation, serialized in seconds
+    #[serde(serialize_with = "serialize_secs")]
+    pub duration: Duration,
+    /// Link statistics accumulated during the operation
+    pub link_stats: LinkStats,
+}
+
+impl FlashReport {
+    /// Average write throughput in bytes per second
+    pub fn throughput(&self) -> f64 {
+        if self.duration.is_zero() {
+            0.0
+        } else {
+            self.bytes_written as f64 / self.duration.as_secs_f64()
+        }
+    }
+}
+
+impl Display for FlashReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} bytes written to target 0x{:02X} at 0x{:08X} ({} pages from page {}) in {:.2}s ({:.1} KB/s); link: {}",
+               self.bytes_written, self.target, self.start_address, self.pages_written, self.first_page,
+               self.duration.as_secs_f64(), self.throughput() / 1024.0, self.link_stats)?;
+        if self.buffer_reloads > 0 {
+            write!(f, "; {} buffer slices reloaded", self.buffer_reloads)?;
+        }
+        Ok(())
+    }
+}
+
+/// Report of the comparison between flash content and an image
+///
+/// Returned by [`CFLoader::verify_image`](crate::CFLoader::verify_image).
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    /// The bootloader target that has been verified
+    pub target: u8,
+    /// Address where the image is expected
+    pub start_address: u32,
+    /// Size of the image in bytes
+    pub length: usize,
+    /// Number of bytes that differ between flash and image
+    pub mismatched_bytes: usize,
+    /// Address of the first differing byte, if any
+    pub first_mismatch: Option<u32>,
+    /// Address ranges of the differing bytes, in order
+    pub mismatched_ranges: Vec<Range<u32>>,
+    /// Time taken by the operation, serialized in seconds
+    #[serde(serialize_with = "serialize_secs")]
+    pub duration: Duration,
+    /// Link statistics accumulated during the operation
+    pub link_stats: LinkStats,
+}
+
+impl VerifyReport {
+    /// Check if the flash content matches the image
+    pub fn is_match(&self) -> bool {
+        self.mismatched_bytes == 0
+    }
+}
+
+impl Display for VerifyReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.first_mismatch {
+            None => write!(f, "{} bytes verified on target 0x{:02X} at 0x{:08X}",
+                           self.length, self.target, self.start_address),
+            Some(first) => write!(f, "{} of {} bytes differ on target 0x{:02X} at 0x{:08X} in {} ranges, first mismatch at 0x{:08X}",
+                                  self.mismatched_bytes, self.length, self.target, self.start_address,
+                                  self.mismatched_ranges.len(), first),
+        }
+    }
+}
+
+/// Flash and verification reports of one target of a firmware update
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetUpdate {
+    /// Report of the flash operation
+    pub flash: FlashReport,
+    /// Report of the verification of the flashed image
+    pub verify: VerifyReport,
+}
+
+/// Report of a firmware update
+///
+/// Returned by [`CFLoader::update_firmware`](crate::CFLoader::update_firmware). Targets
+/// without image are `None`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateReport {
+    /// Update of the nRF51 firmware
+    pub nrf51: Option<TargetUpdate>,
+    /// Update of the STM32 firmware
+    pub stm32: Option<TargetUpdate>,
+    /// Time taken by the whole update, serialized in seconds
+    #[serde(serialize_with = "serialize_secs")]
+    pub duration: Duration,
+}
+
+impl Display for UpdateReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (name, update) in [("nRF51", &self.nrf51), ("STM32", &self.stm32)] {
+            if let Some(update) = update {
+                writeln!(f, "{}: {}", name, update.flash)?;
+                writeln!(f, "{}: {}", name, update.verify)?;
+            }
+        }
+        write!(f, "Firmware updated in {:.2}s", self.duration.as_secs_f64())
+    }
+}
+
+// Compares the data read from flash with the expected content, read from `expected`
+pub(crate) struct Comparison<R> {
+    expected: R,
+    buffer: Vec<u8>,
+    address: u32,
+    mismatched_bytes: usize,
+    mismatched_ranges: Vec<Range<u32>>,
+}
+
+impl<R: Read> Comparison<R> {
+    pub(crate) fn new(start_address: u32, expected: R) -> Self {
+        Comparison {
+            expected,
+            buffer: Vec::new(),
+            address: start_address,
+            mismatched_bytes: 0,
+            mismatched_ranges: Vec::new(),
+        }
+    }
+
+    fn mismatch(&mut self, range: Range<u32>) {
+        self.mismatched_bytes += range.len();
+        match self.mismatched_ranges.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => self.mismatched_ranges.push(range),
+        }
+    }
+
+    // Returns the number of mismatched bytes and their ranges, `end_address` is the
+    // address just past the expected content: bytes not read count as mismatches
+    pub(crate) fn finish(mut self, end_address: u32) -> (usize, Vec<Range<u32>>) {
+        if self.address < end_address {
+            self.mismatch(self.address..end_address);
+        }
+        (self.mismatched_bytes, self.mismatched_ranges)
+    }
+}
+
+impl<R: Read> ReadSink for Comparison<R> {
+    async fn write_chunk(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let mut expected = std::mem::take(&mut self.buffer);
+        expected.resize(data.len(), 0);
+        self.expected.read_exact(&mut expected)?;
+        for (offset, (flash_byte, expected_byte)) in data.iter().zip(expected.iter()).enumerate() {
+            if flash_byte != expected_byte {
+                let address = self.address + offset as u32;
+                self.mismatch(address..address + 1);
+            }
+        }
+        self.buffer = expected;
+        self.address += data.len() as u32;
+        Ok(())
+    }
+}
+
+// Serialize a duration as a floating point number of seconds
+pub(crate) fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compare `flash`, read in chunks of 25 bytes, with `expected`
+    async fn compare(flash: &[u8], expected: &[u8]) -> anyhow::Result<(usize, Vec<Range<u32>>)> {
+        let mut comparison = Comparison::new(0x4000, expected);
+        for chunk in flash.chunks(25) {
+            comparison.write_chunk(chunk).await?;
+        }
+        Ok(comparison.finish(0x4000 + expected.len() as u32))
+    }
+
+    #[tokio::test]
+    async fn same_content_has_no_mismatch() {
+        let data: Vec<u8> = (0..100).collect();
+        assert_eq!(compare(&data, &data).await.unwrap(), (0, vec![]));
+    }
+
+    #[tokio::test]
+    async fn mismatches_are_merged_across_chunks() {
+        let expected: Vec<u8> = (0..100).collect();
+        let mut flash = expected.clone();
+        flash[3] = 0xFF;
+        for byte in &mut flash[24..27] {
+            *byte = 0xFF;
+        }
+
+        let (mismatched_bytes, ranges) = compare(&flash, &expected).await.unwrap();
+        assert_eq!(mismatched_bytes, 4);
+        assert_eq!(ranges, [0x4003..0x4004, 0x4018..0x401B]);
+        // The first mismatch of a report is the start of the first range
+        assert_eq!(ranges[0].start, 0x4003);
+    }
+
+    #[tokio::test]
+    async fn bytes_missing_from_flash_are_mismatches() {
+        let expected: Vec<u8> = (0..100).collect();
+        let (mismatched_bytes, ranges) = compare(&expected[..60], &expected).await.unwrap();
+        assert_eq!(mismatched_bytes, 40);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0], 0x403C..0x4064);
+    }
+
+    #[tokio::test]
+    async fn flash_longer_than_expected_is_an_error() {
+        let data: Vec<u8> = (0..100).collect();
+        assert!(compare(&data, &data[..60]).await.is_err());
+    }
+}
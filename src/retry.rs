@@ -0,0 +1,302 @@
+// Retry and timeout policies for the bootloader link
+//
+// The bootloader protocol has no sequence numbers, so every command is
+// retried by the link until the expected answer is received. How hard the
+// link tries depends on the kind of command: queries should answer within
+// a few milliseconds while a flash write can take more than a second.
+
+use std::time::Duration;
+
+/// Class of bootloader command, used to select a [`RetryPolicy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandClass {
+    /// Commands that are answered directly (GET_INFO, READ_FLASH, READ_BUFFER, ...)
+    Query,
+    /// Commands that only expect an ACK (LOAD_BUFFER, SET_ADDRESS, reset and power commands, ...)
+    Send,
+    /// Flash write commands, which can take up to one second to complete
+    Flash,
+}
+
+/// Delay strategy applied between two attempts of a command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+    /// Retry immediately
+    None,
+    /// Wait a fixed delay between attempts
+    Fixed(Duration),
+    /// Double the delay after each attempt, starting at `initial` and capped at `max`
+    Exponential {
+        /// Delay before the second attempt
+        initial: Duration,
+        /// Maximum delay between attempts
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Delay to wait after the failed attempt number `attempt` (0-based)
+    pub fn delay(&self, attempt: usize) -> Duration {
+        match *self {
+            Backoff::None => Duration::ZERO,
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => {
+                let factor = 1u32.checked_shl(attempt.min(31) as u32).unwrap_or(u32::MAX);
+                initial.saturating_mul(factor).min(max)
+            }
+        }
+    }
+}
+
+/// Retry and timeout policy for one class of command
+///
+/// An attempt consists of sending the command until it is acknowledged and then
+/// polling the bootloader until the expected answer is received, all within
+/// `timeout`. If the attempt fails, the command is tried again up to `attempts`
+/// times, waiting according to `backoff` between attempts. If a `deadline` is
+/// set, the command fails as soon as it is exceeded regardless of the number of
+/// attempts left.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use cfloader::{Backoff, RetryPolicy};
+///
+/// // Policy for a marginal link: more attempts, longer timeout and exponential backoff
+/// let policy = RetryPolicy::default()
+///     .with_attempts(20)
+///     .with_timeout(Duration::from_millis(50))
+///     .with_backoff(Backoff::Exponential {
+///         initial: Duration::from_millis(1),
+///         max: Duration::from_millis(100),
+///     })
+///     .with_deadline(Duration::from_secs(5));
+/// assert_eq!(policy.attempts(), 20);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    attempts: usize,
+    timeout: Duration,
+    poll_interval: Duration,
+    backoff: Backoff,
+    deadline: Option<Duration>,
+}
+
+// Maximum number of attempts for a command
+const DEFAULT_ATTEMPTS: usize = 10;
+// Delay between two radio packets within an attempt
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1);
+// Default short timeout for bootloader operations that should return directly
+const QUERY_TIMEOUT: Duration = Duration::from_millis(10);
+// Timeout for commands only waiting for an ACK
+const SEND_TIMEOUT: Duration = Duration::from_millis(1000);
+// Timeout for flash operation, flash operation can take up to one second to complete
+const FLASH_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: DEFAULT_ATTEMPTS,
+            timeout: QUERY_TIMEOUT,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            backoff: Backoff::None,
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Default policy for a class of command
+    pub fn for_class(class: CommandClass) -> Self {
+        match class {
+            CommandClass::Query => RetryPolicy::default(),
+            CommandClass::Send => RetryPolicy::default().with_timeout(SEND_TIMEOUT),
+            CommandClass::Flash => RetryPolicy::default().with_timeout(FLASH_TIMEOUT),
+        }
+    }
+
+    /// Set the maximum number of attempts (at least one attempt is always made)
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Set the timeout of each attempt
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the delay between two radio packets within an attempt
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Set the delay strategy between attempts
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set the total time allowed for the command, all attempts included
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Remove the total time limit of the command
+    pub fn without_deadline(mut self) -> Self {
+        self.deadline = None;
+        self
+    }
+
+    /// Maximum number of attempts
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    /// Timeout of each attempt
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Delay between two radio packets within an attempt
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// Delay strategy between attempts
+    pub fn backoff(&self) -> Backoff {
+        self.backoff
+    }
+
+    /// Total time allowed for the command, if any
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    // Timeout of an attempt, cut to the time left before the deadline
+    pub(crate) fn attempt_timeout(&self, remaining: Option<Duration>) -> Duration {
+        match remaining {
+            Some(remaining) => self.timeout.min(remaining),
+            None => self.timeout,
+        }
+    }
+
+    // Delay to wait after the failed attempt number `attempt` (0-based), or None if
+    // the deadline is reached before the next attempt can start
+    pub(crate) fn retry_delay(&self, attempt: usize, remaining: Option<Duration>) -> Option<Duration> {
+        let delay = self.backoff.delay(attempt);
+        match remaining {
+            Some(remaining) if delay >= remaining => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+/// Set of retry policies, one per [`CommandClass`]
+///
+/// The default values match the historical behaviour of the link: 10 attempts
+/// of 10 ms for queries, 1 s for sends and 2 s for flash writes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicies {
+    /// Policy for commands that are answered directly
+    pub query: RetryPolicy,
+    /// Policy for commands that only expect an ACK
+    pub send: RetryPolicy,
+    /// Policy for flash write commands
+    pub flash: RetryPolicy,
+}
+
+impl Default for RetryPolicies {
+    fn default() -> Self {
+        RetryPolicies {
+            query: RetryPolicy::for_class(CommandClass::Query),
+            send: RetryPolicy::for_class(CommandClass::Send),
+            flash: RetryPolicy::for_class(CommandClass::Flash),
+        }
+    }
+}
+
+impl RetryPolicies {
+    /// Get the policy for a class of command
+    pub fn get(&self, class: CommandClass) -> &RetryPolicy {
+        match class {
+            CommandClass::Query => &self.query,
+            CommandClass::Send => &self.send,
+            CommandClass::Flash => &self.flash,
+        }
+    }
+
+    /// Get a mutable reference to the policy for a class of command
+    pub fn get_mut(&mut self, class: CommandClass) -> &mut RetryPolicy {
+        match class {
+            CommandClass::Query => &mut self.query,
+            CommandClass::Send => &mut self.send,
+            CommandClass::Flash => &mut self.flash,
+        }
+    }
+
+    /// Replace the policy for a class of command
+    pub fn set(&mut self, class: CommandClass, policy: RetryPolicy) {
+        *self.get_mut(class) = policy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn no_and_fixed_backoff_do_not_grow() {
+        assert_eq!(Backoff::None.delay(0), Duration::ZERO);
+        assert_eq!(Backoff::None.delay(100), Duration::ZERO);
+        assert_eq!(Backoff::Fixed(5 * MS).delay(0), 5 * MS);
+        assert_eq!(Backoff::Fixed(5 * MS).delay(100), 5 * MS);
+    }
+
+    #[test]
+    fn exponential_backoff_doubles_up_to_max() {
+        let backoff = Backoff::Exponential { initial: MS, max: 10 * MS };
+        let delays: Vec<Duration> = (0..6).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(delays, [MS, 2 * MS, 4 * MS, 8 * MS, 10 * MS, 10 * MS]);
+    }
+
+    #[test]
+    fn exponential_backoff_does_not_overflow() {
+        let backoff = Backoff::Exponential { initial: Duration::from_secs(1), max: Duration::from_secs(60) };
+        assert_eq!(backoff.delay(31), Duration::from_secs(60));
+        assert_eq!(backoff.delay(usize::MAX), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn attempt_timeout_is_cut_to_the_deadline() {
+        let policy = RetryPolicy::default().with_timeout(10 * MS).with_deadline(100 * MS);
+        assert_eq!(policy.attempt_timeout(None), 10 * MS);
+        assert_eq!(policy.attempt_timeout(Some(50 * MS)), 10 * MS);
+        assert_eq!(policy.attempt_timeout(Some(3 * MS)), 3 * MS);
+        assert_eq!(policy.attempt_timeout(Some(Duration::ZERO)), Duration::ZERO);
+    }
+
+    #[test]
+    fn retry_stops_when_the_delay_reaches_the_deadline() {
+        let policy = RetryPolicy::default().with_backoff(Backoff::Fixed(5 * MS));
+        assert_eq!(policy.retry_delay(0, None), Some(5 * MS));
+        assert_eq!(policy.retry_delay(0, Some(6 * MS)), Some(5 * MS));
+        assert_eq!(policy.retry_delay(0, Some(5 * MS)), None);
+        assert_eq!(policy.retry_delay(0, Some(Duration::ZERO)), None);
+
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.retry_delay(3, Some(MS)), Some(Duration::ZERO));
+        assert_eq!(policy.retry_delay(3, Some(Duration::ZERO)), None);
+    }
+
+    #[test]
+    fn at_least_one_attempt_is_made() {
+        assert_eq!(RetryPolicy::default().with_attempts(0).attempts(), 1);
+    }
+}
@@ -0,0 +1,38 @@
+// Destinations of the data read from flash
+//
+// Flash reads deliver their data chunk by chunk, as the bootloader answers.
+// A sink consumes each chunk as it arrives so that long reads do not have to
+// hold the whole flash content in memory when they only hash or compare it.
+
+// Receives the data of a flash read as it arrives
+pub(crate) trait ReadSink {
+    // Consume the next chunk of data, in flash order
+    async fn write_chunk(&mut self, data: &[u8]) -> anyhow::Result<()>;
+}
+
+impl ReadSink for Vec<u8> {
+    async fn write_chunk(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+// Writes the data to a blocking writer
+pub(crate) struct WriteSink<'a, W>(pub(crate) &'a mut W);
+
+impl<W: std::io::Write> ReadSink for WriteSink<'_, W> {
+    async fn write_chunk(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.0.write_all(data)?;
+        Ok(())
+    }
+}
+
+// Writes the data to an async writer
+pub(crate) struct AsyncWriteSink<'a, W>(pub(crate) &'a mut W);
+
+impl<W: tokio::io::AsyncWrite + Unpin> ReadSink for AsyncWriteSink<'_, W> {
+    async fn write_chunk(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        tokio::io::AsyncWriteExt::write_all(self.0, data).await?;
+        Ok(())
+    }
+}
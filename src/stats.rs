@@ -0,0 +1,216 @@
+// Link quality statistics collected by the bootloader link
+//
+// The statistics are meant to tell apart a bad radio link from a slow
+// bootloader: ACK losses and retries point to the radio while long round-trip
+// times with many polls point to the bootloader taking time to answer.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Upper bounds of the round-trip time histogram buckets, in milliseconds
+///
+/// The last bucket of [`RttHistogram`] counts all round-trips longer than the last bound.
+pub const RTT_BUCKETS_MS: [u64; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+/// Histogram of command round-trip times
+///
+/// A round-trip is measured from the first transmission of a command attempt to the
+/// reception of its expected answer (or ACK for commands that do not expect an answer).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RttHistogram {
+    counts: [u64; RTT_BUCKETS_MS.len() + 1],
+}
+
+impl RttHistogram {
+    /// Record a round-trip time
+    pub fn record(&mut self, rtt: Duration) {
+        let ms = rtt.as_millis();
+        let bucket = RTT_BUCKETS_MS
+            .iter()
+            .position(|&bound| ms < bound as u128)
+            .unwrap_or(RTT_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+    }
+
+    /// Number of round-trips in each bucket
+    ///
+    /// Bucket `i` counts round-trips shorter than `RTT_BUCKETS_MS[i]` and at least
+    /// `RTT_BUCKETS_MS[i - 1]`. The last bucket counts everything above the last bound.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Total number of round-trips recorded
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    fn since(&self, earlier: &RttHistogram) -> RttHistogram {
+        let mut counts = self.counts;
+        for (count, earlier) in counts.iter_mut().zip(earlier.counts.iter()) {
+            *count = count.saturating_sub(*earlier);
+        }
+        RttHistogram { counts }
+    }
+}
+
+/// Statistics for one bootloader command
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CommandStats {
+    /// Number of times the command was issued
+    pub commands: u64,
+    /// Number of additional attempts made after a failed attempt
+    pub retries: u64,
+    /// Number of times the command failed after all attempts
+    pub failures: u64,
+}
+
+impl CommandStats {
+    fn since(&self, earlier: &CommandStats) -> CommandStats {
+        CommandStats {
+            commands: self.commands.saturating_sub(earlier.commands),
+            retries: self.retries.saturating_sub(earlier.retries),
+            failures: self.failures.saturating_sub(earlier.failures),
+        }
+    }
+}
+
+/// Snapshot of the link quality statistics of a [`Bllink`](crate::Bllink)
+///
+/// Commands are identified by their bootloader command byte (the third byte of the
+/// packet, for example `0x18` for WRITE_FLASH).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LinkStats {
+    /// Number of radio packets sent, including polls
+    pub packets_sent: u64,
+    /// Number of radio packets that have been acknowledged
+    pub acks_received: u64,
+    /// Number of poll packets sent while waiting for a response
+    pub polls: u64,
+    /// Per command statistics, indexed by command byte
+    pub commands: BTreeMap<u8, CommandStats>,
+    /// Round-trip time histogram of successful attempts
+    pub rtt: RttHistogram,
+}
+
+impl LinkStats {
+    /// Ratio of sent packets that have not been acknowledged, between 0 and 1
+    pub fn ack_loss_rate(&self) -> f64 {
+        if self.packets_sent == 0 {
+            0.0
+        } else {
+            1.0 - (self.acks_received as f64 / self.packets_sent as f64)
+        }
+    }
+
+    /// Total number of retries, all commands included
+    pub fn total_retries(&self) -> u64 {
+        self.commands.values().map(|stats| stats.retries).sum()
+    }
+
+    /// Statistics accumulated since an earlier snapshot of the same link
+    ///
+    /// This is useful to get the statistics of a single operation without resetting
+    /// the statistics of the link.
+    pub fn since(&self, earlier: &LinkStats) -> LinkStats {
+        let commands = self
+            .commands
+            .iter()
+            .map(|(command, stats)| {
+                let earlier = earlier.commands.get(command).copied().unwrap_or_default();
+                (*command, stats.since(&earlier))
+            })
+            .filter(|(_, stats)| *stats != CommandStats::default())
+            .collect();
+
+        LinkStats {
+            packets_sent: self.packets_sent.saturating_sub(earlier.packets_sent),
+            acks_received: self.acks_received.saturating_sub(earlier.acks_received),
+            polls: self.polls.saturating_sub(earlier.polls),
+            commands,
+            rtt: self.rtt.since(&earlier.rtt),
+        }
+    }
+
+    pub(crate) fn record_command(&mut self, command: u8, attempts: usize, success: bool) {
+        let stats = self.commands.entry(command).or_default();
+        stats.commands += 1;
+        stats.retries += attempts.saturating_sub(1) as u64;
+        if !success {
+            stats.failures += 1;
+        }
+    }
+}
+
+impl Display for LinkStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} packets sent, {} acked ({:.1}% loss), {} polls, {} retries",
+               self.packets_sent, self.acks_received, self.ack_loss_rate() * 100.0,
+               self.polls, self.total_retries())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtt_bucket_bounds_are_exclusive() {
+        let mut histogram = RttHistogram::default();
+        histogram.record(Duration::from_micros(999));
+        histogram.record(Duration::from_millis(1));
+        histogram.record(Duration::from_micros(1999));
+        histogram.record(Duration::from_millis(999));
+        histogram.record(Duration::from_millis(1000));
+        histogram.record(Duration::from_secs(60));
+
+        assert_eq!(histogram.counts(), [1, 2, 0, 0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(histogram.total(), 6);
+    }
+
+    #[test]
+    fn commands_are_counted_per_command_byte() {
+        let mut stats = LinkStats::default();
+        stats.record_command(0x1C, 1, true);
+        stats.record_command(0x1C, 3, true);
+        stats.record_command(0x18, 10, false);
+
+        assert_eq!(stats.commands[&0x1C], CommandStats { commands: 2, retries: 2, failures: 0 });
+        assert_eq!(stats.commands[&0x18], CommandStats { commands: 1, retries: 9, failures: 1 });
+        assert_eq!(stats.total_retries(), 11);
+    }
+
+    #[test]
+    fn since_keeps_what_happened_after_the_snapshot() {
+        let mut stats = LinkStats { packets_sent: 10, acks_received: 8, polls: 3, ..Default::default() };
+        stats.record_command(0x10, 1, true);
+        stats.rtt.record(Duration::from_millis(3));
+        let snapshot = stats.clone();
+
+        stats.packets_sent += 5;
+        stats.acks_received += 4;
+        stats.record_command(0x1C, 2, true);
+        stats.rtt.record(Duration::from_millis(30));
+
+        let delta = stats.since(&snapshot);
+        assert_eq!((delta.packets_sent, delta.acks_received, delta.polls), (5, 4, 0));
+        // Commands not issued since the snapshot are left out
+        assert_eq!(delta.commands.keys().copied().collect::<Vec<_>>(), [0x1C]);
+        assert_eq!(delta.total_retries(), 1);
+        assert_eq!(delta.rtt.total(), 1);
+        assert_eq!(delta.rtt.counts()[5], 1);
+        assert!((delta.ack_loss_rate() - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn since_a_later_snapshot_does_not_underflow() {
+        let mut later = LinkStats { packets_sent: 4, ..Default::default() };
+        later.record_command(0x10, 2, true);
+        let delta = LinkStats::default().since(&later);
+        assert_eq!(delta, LinkStats::default());
+        assert_eq!(delta.ack_loss_rate(), 0.0);
+    }
+}
@@ -0,0 +1,279 @@
+// Packet trace recording and replay
+//
+// A trace is the exact radio conversation between the link and the
+// bootloader: every packet sent, whether it was acknowledged and the payload
+// of the ACK. Traces are stored as text, one packet per line:
+//
+//   <timestamp in us> <sent packet in hex> <1 if acked, 0 otherwise> <ack payload in hex or ->
+//
+// Lines starting with '#' are comments.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::hex::{decode_hex, encode_hex};
+
+/// One packet exchange recorded in a [`Trace`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Time of the exchange, relative to the start of the recording
+    pub timestamp: Duration,
+    /// Packet sent to the bootloader
+    pub sent: Vec<u8>,
+    /// Whether the packet has been acknowledged
+    pub acked: bool,
+    /// Payload of the ACK packet, empty if not acknowledged
+    pub response: Vec<u8>,
+}
+
+impl TraceEntry {
+    /// Parse one line of a trace file
+    ///
+    /// Returns `Ok(None)` for comments and empty lines.
+    pub fn parse(line: &str) -> anyhow::Result<Option<Self>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 4 {
+            return Err(anyhow::anyhow!("Invalid trace line, expected 4 fields: '{}'", line));
+        }
+
+        let timestamp = fields[0].parse::<u64>()
+            .map_err(|e| anyhow::anyhow!("Invalid trace timestamp '{}': {}", fields[0], e))?;
+        let acked = match fields[2] {
+            "0" => false,
+            "1" => true,
+            other => return Err(anyhow::anyhow!("Invalid trace ACK status '{}'", other)),
+        };
+
+        Ok(Some(TraceEntry {
+            timestamp: Duration::from_micros(timestamp),
+            sent: parse_hex_field(fields[1])?,
+            acked,
+            response: parse_hex_field(fields[3])?,
+        }))
+    }
+
+    /// Format the entry as one line of a trace file, without line terminator
+    pub fn to_line(&self) -> String {
+        format!("{} {} {} {}",
+                self.timestamp.as_micros(), hex_field(&self.sent),
+                if self.acked { 1 } else { 0 }, hex_field(&self.response))
+    }
+}
+
+/// A recorded radio conversation
+///
+/// Traces are recorded with [`Bllink::record_trace`](crate::Bllink::record_trace) and can be
+/// fed back to the bootloader code with [`Bllink::replay`](crate::Bllink::replay).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    /// Recorded packet exchanges, in order
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    /// Load a trace from a file
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path.as_ref())
+            .map_err(|e| anyhow::anyhow!("Cannot open trace file {}: {}", path.as_ref().display(), e))?;
+        Self::from_reader(std::io::BufReader::new(file))
+    }
+
+    /// Read a trace from any buffered reader
+    pub fn from_reader(reader: impl BufRead) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if let Some(entry) = TraceEntry::parse(&line)
+                .map_err(|e| e.context(format!("Trace line {}", line_number + 1)))? {
+                entries.push(entry);
+            }
+        }
+        Ok(Trace { entries })
+    }
+}
+
+// Records the packets exchanged by a link into a writer
+pub(crate) struct TraceRecorder {
+    writer: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+impl TraceRecorder {
+    pub(crate) fn new(mut writer: Box<dyn Write + Send>, address: &[u8; 5], channel: u8) -> anyhow::Result<Self> {
+        writeln!(writer, "# cfloader trace, address {}, channel {}", encode_hex(address), channel)?;
+        Ok(TraceRecorder { writer, start: Instant::now() })
+    }
+
+    pub(crate) fn record(&mut self, sent: &[u8], acked: bool, response: &[u8]) -> anyhow::Result<()> {
+        let entry = TraceEntry {
+            timestamp: self.start.elapsed(),
+            sent: sent.to_vec(),
+            acked,
+            response: response.to_vec(),
+        };
+        writeln!(self.writer, "{}", entry.to_line())?;
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Plays back a trace in place of the radio
+pub(crate) struct TraceReplay {
+    entries: VecDeque<TraceEntry>,
+    start: Option<Instant>,
+}
+
+impl TraceReplay {
+    pub(crate) fn new(trace: Trace) -> Self {
+        TraceReplay { entries: trace.entries.into(), start: None }
+    }
+
+    // Returns the recorded answer for the next packet
+    //
+    // The answer is delayed to match the timing of the recording so that the
+    // timeouts of the link expire at the same points as in the recorded run.
+    pub(crate) async fn exchange(&mut self, sent: &[u8]) -> anyhow::Result<(bool, Vec<u8>)> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+
+        let entry = self.entries.pop_front()
+            .ok_or_else(|| anyhow::anyhow!("End of trace reached while sending {:02X?}", sent))?;
+
+        if entry.sent != sent {
+            return Err(anyhow::anyhow!(
+                "Replay diverged from trace at {:?}: sent {:02X?} but trace recorded {:02X?}",
+                entry.timestamp, sent, entry.sent
+            ));
+        }
+
+        tokio::time::sleep_until((start + entry.timestamp).into()).await;
+
+        Ok((entry.acked, entry.response))
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+// Hex field of a trace line, which cannot be empty
+fn hex_field(data: &[u8]) -> String {
+    if data.is_empty() {
+        "-".to_string()
+    } else {
+        encode_hex(data)
+    }
+}
+
+fn parse_hex_field(field: &str) -> anyhow::Result<Vec<u8>> {
+    if field == "-" {
+        Ok(Vec::new())
+    } else {
+        decode_hex(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_round_trips_through_text() {
+        let entry = TraceEntry {
+            timestamp: Duration::from_micros(1234),
+            sent: vec![0xFF, 0xFF, 0x10],
+            acked: true,
+            response: vec![0xFF, 0xFF, 0x10, 0x00, 0x04],
+        };
+        assert_eq!(entry.to_line(), "1234 ffff10 1 ffff100004");
+        assert_eq!(TraceEntry::parse(&entry.to_line()).unwrap(), Some(entry));
+    }
+
+    #[test]
+    fn unacked_entry_round_trips_through_text() {
+        let entry = TraceEntry {
+            timestamp: Duration::ZERO,
+            sent: vec![0xFF],
+            acked: false,
+            response: Vec::new(),
+        };
+        assert_eq!(entry.to_line(), "0 ff 0 -");
+        assert_eq!(TraceEntry::parse(&entry.to_line()).unwrap(), Some(entry));
+    }
+
+    #[test]
+    fn recorded_trace_round_trips_through_reader() {
+        let buffer = SharedBuffer::default();
+        let mut recorder = TraceRecorder::new(Box::new(buffer.clone()), &[0xE7; 5], 0).unwrap();
+        recorder.record(&[0xFF, 0xFE, 0x10], false, &[]).unwrap();
+        recorder.record(&[0xFF, 0xFE, 0x10], true, &[0xFF, 0xFE, 0x10, 0x01]).unwrap();
+        recorder.finish().unwrap();
+
+        let text = buffer.0.lock().unwrap().clone();
+        assert!(text.starts_with(b"# cfloader trace, address e7e7e7e7e7, channel 0\n"));
+
+        let trace = Trace::from_reader(text.as_slice()).unwrap();
+        assert_eq!(trace.entries.len(), 2);
+        assert!(!trace.entries[0].acked);
+        assert_eq!(trace.entries[1].sent, [0xFF, 0xFE, 0x10]);
+        assert_eq!(trace.entries[1].response, [0xFF, 0xFE, 0x10, 0x01]);
+        assert!(trace.entries[0].timestamp <= trace.entries[1].timestamp);
+    }
+
+    #[test]
+    fn comments_and_empty_lines_are_skipped() {
+        assert_eq!(TraceEntry::parse("# comment").unwrap(), None);
+        assert_eq!(TraceEntry::parse("   ").unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        assert!(TraceEntry::parse("0 FF 1").is_err());
+        assert!(TraceEntry::parse("x FF 1 -").is_err());
+        assert!(TraceEntry::parse("0 FF 2 -").is_err());
+        assert!(TraceEntry::parse("0 FFF 1 -").is_err());
+        assert!(TraceEntry::parse("0 GG 1 -").is_err());
+
+        let error = Trace::from_reader("0 FF 1 -\n0 FF 1\n".as_bytes()).unwrap_err();
+        assert!(format!("{:#}", error).contains("Trace line 2"));
+    }
+
+    #[tokio::test]
+    async fn replay_reports_divergence_and_end_of_trace() {
+        let trace = Trace::from_reader("0 FFFF10 1 FFFF10\n".as_bytes()).unwrap();
+
+        let mut replay = TraceReplay::new(trace.clone());
+        let error = replay.exchange(&[0xFF, 0xFE, 0x10]).await.unwrap_err();
+        assert!(error.to_string().contains("Replay diverged from trace"));
+
+        let mut replay = TraceReplay::new(trace);
+        assert_eq!(replay.exchange(&[0xFF, 0xFF, 0x10]).await.unwrap(), (true, vec![0xFF, 0xFF, 0x10]));
+        assert_eq!(replay.remaining(), 0);
+        let error = replay.exchange(&[0xFF]).await.unwrap_err();
+        assert!(error.to_string().contains("End of trace reached"));
+    }
+
+    // Writer keeping what the recorder wrote readable after the recorder is gone
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}